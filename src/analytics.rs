@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::error;
+use crate::config;
+use crate::metrics::metrics;
+use crate::order_book::UnifiedOrderBook;
+use crate::types::{BookSnapshot, OrderSide, PriceLevel, VenueSequences};
+
+/// Which liquidity figures to compute.
+#[derive(Debug, Clone)]
+pub struct AnalyticsOptions {
+    /// Distances from mid, in basis points, to total depth within.
+    pub bands_bps: Vec<f64>,
+    /// Order sizes, in base units, for the impact curve and venue comparison.
+    pub sizes: Vec<f64>,
+    /// Taker fee per venue, as a fraction of notional, charged in the venue
+    /// comparison. Venues missing here are compared as if free.
+    pub taker_fees: HashMap<String, f64>,
+}
+
+impl Default for AnalyticsOptions {
+    fn default() -> Self {
+        Self {
+            bands_bps: config::ANALYTICS_BANDS_BPS.to_vec(),
+            sizes: config::ANALYTICS_SIZES.to_vec(),
+            taker_fees: HashMap::new(),
+        }
+    }
+}
+
+/// Liquidity in the unified book at one moment.
+#[derive(Debug, Serialize, Clone)]
+pub struct LiquidityReport {
+    pub timestamp_ms: u64,
+    pub mid_price: f64,
+    pub spread_bps: f64,
+    pub depth: Vec<DepthBand>,
+    pub impact: Vec<ImpactPoint>,
+    pub savings: Vec<VenueSavings>,
+    pub venue_sequences: VenueSequences,
+}
+
+/// Volume resting within `bps` of mid, consolidated and per venue.
+#[derive(Debug, Serialize, Clone)]
+pub struct DepthBand {
+    pub bps: f64,
+    pub bid_volume: f64,
+    pub ask_volume: f64,
+    /// `(bid - ask) / (bid + ask)`: positive when buyers outweigh sellers.
+    pub imbalance: f64,
+    pub venues: Vec<VenueDepth>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VenueDepth {
+    pub exchange: String,
+    pub bid_volume: f64,
+    pub ask_volume: f64,
+}
+
+/// What a market order of `size` would pay across every venue, before fees.
+#[derive(Debug, Serialize, Clone)]
+pub struct ImpactPoint {
+    pub side: OrderSide,
+    pub size: f64,
+    /// Less than `size` when the book runs out.
+    pub filled: f64,
+    pub vwap: f64,
+    /// VWAP distance from mid against the taker.
+    pub impact_bps: f64,
+}
+
+/// How much better the consolidated book fills `size` than one venue alone.
+/// Both VWAPs include each venue's taker fee, so a cheaper venue can beat
+/// better prices elsewhere.
+#[derive(Debug, Serialize, Clone)]
+pub struct VenueSavings {
+    pub side: OrderSide,
+    pub size: f64,
+    pub exchange: String,
+    pub consolidated_vwap: f64,
+    /// `None` when the venue alone cannot fill `size`.
+    pub venue_vwap: Option<f64>,
+    pub savings_bps: Option<f64>,
+    /// Total saved in the quote currency.
+    pub savings: Option<f64>,
+}
+
+/// Computes a report from a full-depth book, or `None` while either side is empty.
+pub fn analyze(book: &BookSnapshot, options: &AnalyticsOptions) -> Option<LiquidityReport> {
+    let best_bid = book.bids.first()?.price;
+    let best_ask = book.asks.first()?.price;
+    let mid = (best_bid + best_ask) / 2.0;
+
+    let mut venues: Vec<&str> = book
+        .bids
+        .iter()
+        .chain(&book.asks)
+        .flat_map(|level| level.venues.iter().map(|venue| venue.exchange.as_str()))
+        .collect();
+    venues.sort_unstable();
+    venues.dedup();
+
+    let depth = options
+        .bands_bps
+        .iter()
+        .map(|&bps| {
+            let floor = mid * (1.0 - bps / 10_000.0);
+            let ceiling = mid * (1.0 + bps / 10_000.0);
+            let bids: Vec<&PriceLevel> = book.bids.iter().take_while(|level| level.price >= floor).collect();
+            let asks: Vec<&PriceLevel> = book.asks.iter().take_while(|level| level.price <= ceiling).collect();
+
+            let bid_volume: f64 = bids.iter().map(|level| level.total_volume).sum();
+            let ask_volume: f64 = asks.iter().map(|level| level.total_volume).sum();
+            let total = bid_volume + ask_volume;
+            DepthBand {
+                bps,
+                bid_volume,
+                ask_volume,
+                imbalance: if total > 0.0 { (bid_volume - ask_volume) / total } else { 0.0 },
+                venues: venues
+                    .iter()
+                    .map(|&exchange| VenueDepth {
+                        exchange: exchange.to_string(),
+                        bid_volume: venue_volume(&bids, exchange),
+                        ask_volume: venue_volume(&asks, exchange),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let mut impact = Vec::new();
+    let mut savings = Vec::new();
+    for (side, levels) in [(OrderSide::Buy, &book.asks), (OrderSide::Sell, &book.bids)] {
+        for &size in &options.sizes {
+            let Some((filled, vwap)) = walk(levels, &side, size, None, &HashMap::new()) else {
+                continue;
+            };
+            impact.push(ImpactPoint {
+                side: side.clone(),
+                size,
+                filled,
+                vwap,
+                impact_bps: against_taker(&side, vwap, mid) / mid * 10_000.0,
+            });
+
+            // Comparing against a venue only means something once the book fills the size.
+            if filled < size {
+                continue;
+            }
+            let Some((_, consolidated_vwap)) = walk(levels, &side, size, None, &options.taker_fees) else {
+                continue;
+            };
+            for &exchange in &venues {
+                let venue_vwap = walk(levels, &side, size, Some(exchange), &options.taker_fees)
+                    .filter(|&(venue_filled, _)| venue_filled >= size)
+                    .map(|(_, venue_vwap)| venue_vwap);
+                let saved = venue_vwap.map(|venue_vwap| against_taker(&side, venue_vwap, consolidated_vwap));
+                savings.push(VenueSavings {
+                    side: side.clone(),
+                    size,
+                    exchange: exchange.to_string(),
+                    consolidated_vwap,
+                    venue_vwap,
+                    savings_bps: saved.zip(venue_vwap).map(|(saved, venue_vwap)| saved / venue_vwap * 10_000.0),
+                    savings: saved.map(|saved| saved * size),
+                });
+            }
+        }
+    }
+
+    Some(LiquidityReport {
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64,
+        mid_price: mid,
+        spread_bps: (best_ask - best_bid) / mid * 10_000.0,
+        depth,
+        impact,
+        savings,
+        venue_sequences: book.venue_sequences.clone(),
+    })
+}
+
+/// How much worse `price` is than `reference` for a taker on `side`.
+fn against_taker(side: &OrderSide, price: f64, reference: f64) -> f64 {
+    match side {
+        OrderSide::Buy => price - reference,
+        OrderSide::Sell => reference - price,
+    }
+}
+
+fn venue_volume(levels: &[&PriceLevel], exchange: &str) -> f64 {
+    levels
+        .iter()
+        .flat_map(|level| &level.venues)
+        .filter(|venue| venue.exchange == exchange)
+        .map(|venue| venue.volume)
+        .sum()
+}
+
+/// Fills up to `size` for a taker on `side`, best price first and in the
+/// book's fill order within a level, from one venue or all of them. Returns
+/// the volume filled with its VWAP, each venue's price carrying its fee from
+/// `taker_fees`. `None` when nothing fills.
+fn walk(
+    levels: &[PriceLevel],
+    side: &OrderSide,
+    size: f64,
+    exchange: Option<&str>,
+    taker_fees: &HashMap<String, f64>,
+) -> Option<(f64, f64)> {
+    let mut filled = 0.0;
+    let mut cost = 0.0;
+    'levels: for level in levels {
+        for venue in &level.venues {
+            if exchange.is_some_and(|exchange| venue.exchange != exchange) {
+                continue;
+            }
+            let take = venue.volume.min(size - filled);
+            let fee = taker_fees.get(&venue.exchange).copied().unwrap_or(0.0);
+            // Buyers pay the fee on top of the price; sellers get it taken off.
+            let price = match side {
+                OrderSide::Buy => level.price * (1.0 + fee),
+                OrderSide::Sell => level.price * (1.0 - fee),
+            };
+            filled += take;
+            cost += take * price;
+            if filled >= size {
+                break 'levels;
+            }
+        }
+    }
+    (filled > 0.0).then(|| (filled, cost / filled))
+}
+
+/// Analyzes the full book every `interval` and publishes each report, also
+/// as Prometheus gauges. Stops once every receiver is dropped.
+pub fn spawn_publisher(
+    order_book: Arc<UnifiedOrderBook>,
+    options: AnalyticsOptions,
+    interval: Duration,
+) -> watch::Receiver<Option<Arc<LiquidityReport>>> {
+    let (sender, receiver) = watch::channel(None);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = sender.closed() => break,
+            }
+
+            let book = match order_book.get_book(usize::MAX).await {
+                Ok(book) => book,
+                Err(e) => {
+                    error!(error = %e, error_kind = "order_book", "Error reading order book for analytics");
+                    continue;
+                }
+            };
+            if let Some(report) = analyze(&book, &options) {
+                record_metrics(&report);
+                sender.send_replace(Some(Arc::new(report)));
+            }
+        }
+    });
+    receiver
+}
+
+fn record_metrics(report: &LiquidityReport) {
+    let metrics = metrics();
+    // Start from empty so venues, bands and sizes missing from this report
+    // stop being exported.
+    metrics.book_imbalance.reset();
+    metrics.liquidity_depth.reset();
+    metrics.price_impact_bps.reset();
+    metrics.aggregation_savings_bps.reset();
+    for band in &report.depth {
+        let bps = band.bps.to_string();
+        metrics.book_imbalance.with_label_values(&[&bps]).set(band.imbalance);
+        for venue in &band.venues {
+            metrics.liquidity_depth.with_label_values(&[&venue.exchange, "bid", &bps]).set(venue.bid_volume);
+            metrics.liquidity_depth.with_label_values(&[&venue.exchange, "ask", &bps]).set(venue.ask_volume);
+        }
+    }
+    for point in &report.impact {
+        metrics
+            .price_impact_bps
+            .with_label_values(&[side_label(&point.side), &point.size.to_string()])
+            .set(point.impact_bps);
+    }
+    for saving in &report.savings {
+        if let Some(savings_bps) = saving.savings_bps {
+            metrics
+                .aggregation_savings_bps
+                .with_label_values(&[&saving.exchange, side_label(&saving.side), &saving.size.to_string()])
+                .set(savings_bps);
+        }
+    }
+}
+
+fn side_label(side: &OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "buy",
+        OrderSide::Sell => "sell",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::VenueLevel;
+
+    fn level(price: f64, venues: &[(&str, f64)]) -> PriceLevel {
+        PriceLevel {
+            price,
+            total_volume: venues.iter().map(|(_, volume)| volume).sum(),
+            venues: venues
+                .iter()
+                .map(|&(exchange, volume)| VenueLevel { exchange: exchange.to_string(), volume })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn reports_depth_impact_and_savings() {
+        let book = BookSnapshot {
+            bids: vec![level(99.0, &[("Kraken", 2.0)]), level(98.0, &[("Bybit", 2.0)])],
+            asks: vec![level(101.0, &[("Kraken", 1.0), ("Bybit", 1.0)]), level(103.0, &[("Kraken", 2.0)])],
+            venue_sequences: VenueSequences::new(),
+        };
+        let options = AnalyticsOptions { bands_bps: vec![150.0], sizes: vec![2.0], taker_fees: HashMap::new() };
+        let report = analyze(&book, &options).unwrap();
+
+        assert_eq!(report.mid_price, 100.0);
+        // Within 1.5% of 100: the 99 bid and the 101 ask.
+        let band = &report.depth[0];
+        assert_eq!((band.bid_volume, band.ask_volume), (2.0, 2.0));
+        assert_eq!(band.imbalance, 0.0);
+        assert_eq!(band.venues[0].exchange, "Bybit");
+        assert_eq!(band.venues[0].ask_volume, 1.0);
+
+        let buy = report.impact.iter().find(|point| matches!(point.side, OrderSide::Buy)).unwrap();
+        assert_eq!((buy.vwap, buy.impact_bps), (101.0, 100.0));
+
+        // Kraken alone buys 2 at an average of 102 against 101 consolidated;
+        // Bybit alone cannot fill 2.
+        let kraken = report.savings.iter().find(|saving| matches!(saving.side, OrderSide::Buy) && saving.exchange == "Kraken").unwrap();
+        assert_eq!(kraken.venue_vwap, Some(102.0));
+        assert_eq!(kraken.savings, Some(2.0));
+        let bybit = report.savings.iter().find(|saving| matches!(saving.side, OrderSide::Buy) && saving.exchange == "Bybit").unwrap();
+        assert_eq!(bybit.venue_vwap, None);
+    }
+    #[test]
+    fn savings_charge_each_venue_its_taker_fee() {
+        let book = BookSnapshot {
+            bids: vec![level(99.0, &[("Kraken", 2.0)])],
+            asks: vec![level(100.0, &[("Bybit", 1.0)]), level(100.5, &[("Kraken", 2.0)])],
+            venue_sequences: VenueSequences::new(),
+        };
+        let taker_fees = HashMap::from([("Bybit".to_string(), 0.01), ("Kraken".to_string(), 0.0)]);
+        let options = AnalyticsOptions { bands_bps: vec![], sizes: vec![2.0], taker_fees };
+        let report = analyze(&book, &options).unwrap();
+
+        // Impact stays before fees.
+        let buy = report.impact.iter().find(|point| matches!(point.side, OrderSide::Buy)).unwrap();
+        assert_eq!(buy.vwap, 100.25);
+
+        // Bybit's 1% fee makes its 100 cost 101, so consolidated averages
+        // 100.75 and Kraken alone at 100.5 is the better fill.
+        let kraken = report.savings.iter().find(|saving| matches!(saving.side, OrderSide::Buy) && saving.exchange == "Kraken").unwrap();
+        assert!((kraken.consolidated_vwap - 100.75).abs() < 1e-9);
+        assert_eq!(kraken.venue_vwap, Some(100.5));
+        assert!((kraken.savings.unwrap() + 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{net::SocketAddr, pin::Pin, time::Duration};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{info, warn};
+use crate::{
+    api::http::{ApiError, ApiState},
+    errors::OrderBookError,
+    router,
+    types,
+};
+
+pub mod proto {
+    tonic::include_proto!("blockfinders");
+}
+
+use proto::block_finders_server::{BlockFinders, BlockFindersServer};
+
+const DEFAULT_TOP_OF_BOOK_INTERVAL_MS: u32 = 250;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::NotFound(message) => Status::not_found(message),
+            ApiError::Unauthorized => Status::unauthenticated("Missing or invalid bearer token"),
+            ApiError::Forbidden(message) => Status::permission_denied(message),
+            ApiError::Unavailable(message) => Status::unavailable(message),
+            ApiError::OrderBook(e) => e.into(),
+        }
+    }
+}
+
+impl From<OrderBookError> for Status {
+    fn from(e: OrderBookError) -> Self {
+        match e {
+            OrderBookError::EmptyOrderBook | OrderBookError::InsufficientVolume(_) => {
+                Status::failed_precondition(e.to_string())
+            }
+            _ => Status::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<types::OrderSide> for proto::Side {
+    fn from(side: types::OrderSide) -> Self {
+        match side {
+            types::OrderSide::Buy => proto::Side::Buy,
+            types::OrderSide::Sell => proto::Side::Sell,
+        }
+    }
+}
+
+fn order_request(request: proto::OrderRequest) -> Result<types::OrderRequest, Status> {
+    let side = match request.side() {
+        proto::Side::Buy => types::OrderSide::Buy,
+        proto::Side::Sell => types::OrderSide::Sell,
+        proto::Side::Unspecified => return Err(Status::invalid_argument("side is required")),
+    };
+    let unit = match request.unit() {
+        proto::VolumeUnit::Base => types::VolumeUnit::Base,
+        proto::VolumeUnit::Quote => types::VolumeUnit::Quote,
+    };
+    Ok(types::OrderRequest {
+        symbol: request.symbol,
+        side,
+        volume: request.volume,
+        unit,
+        detailed: request.detailed,
+    })
+}
+
+impl From<types::PriceResponse> for proto::PriceResponse {
+    fn from(quote: types::PriceResponse) -> Self {
+        proto::PriceResponse {
+            symbol: quote.symbol,
+            total_volume: quote.total_volume,
+            side: proto::Side::from(quote.side).into(),
+            venue_volumes: quote.venue_volumes.into_iter().collect(),
+            vwap: quote.vwap,
+            conversion_rates: quote.conversion_rates.into_iter().collect(),
+            fully_filled: quote.fully_filled,
+            detail: quote.detail.map(|detail| proto::QuoteDetail {
+                slices: detail
+                    .slices
+                    .into_iter()
+                    .map(|slice| proto::FillSlice {
+                        exchange: slice.exchange,
+                        price: slice.price,
+                        volume: slice.volume,
+                    })
+                    .collect(),
+                worst_price: detail.worst_price,
+                mid_price: detail.mid_price,
+                slippage_bps: detail.slippage_bps,
+            }),
+        }
+    }
+}
+
+impl From<router::OrderReport> for proto::OrderReport {
+    fn from(report: router::OrderReport) -> Self {
+        proto::OrderReport {
+            dry_run: report.dry_run,
+            quote: Some(report.quote.into()),
+            routes: report
+                .routes
+                .into_iter()
+                .map(|route| proto::RoutedOrder {
+                    exchange: route.exchange,
+                    volume: route.volume,
+                    estimated_fee: route.estimated_fee,
+                    status: route.status,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<types::Fill> for proto::Fill {
+    fn from(fill: types::Fill) -> Self {
+        proto::Fill {
+            exchange: fill.exchange,
+            symbol: fill.symbol,
+            side: proto::Side::from(fill.side).into(),
+            volume: fill.volume,
+            price: fill.price,
+            timestamp_ms: fill.timestamp_ms,
+        }
+    }
+}
+
+fn top_level(levels: &[types::PriceLevel]) -> Option<proto::Level> {
+    levels.first().map(|level| proto::Level {
+        price: level.price,
+        volume: level.total_volume,
+    })
+}
+
+pub struct GrpcService {
+    state: ApiState,
+}
+
+#[tonic::async_trait]
+impl BlockFinders for GrpcService {
+    async fn get_quote(&self, request: Request<proto::OrderRequest>) -> Result<Response<proto::PriceResponse>, Status> {
+        let request = order_request(request.into_inner())?;
+        self.state.check_symbol(&request.symbol)?;
+        let quote = self.state.session.order_book.get_quote(request).await?;
+        Ok(Response::new(quote.into()))
+    }
+
+    type StreamTopOfBookStream = ResponseStream<proto::TopOfBook>;
+
+    async fn stream_top_of_book(
+        &self,
+        request: Request<proto::TopOfBookRequest>,
+    ) -> Result<Response<Self::StreamTopOfBookStream>, Status> {
+        let request = request.into_inner();
+        self.state.check_symbol(&request.symbol)?;
+
+        let interval_ms = match request.interval_ms {
+            0 => DEFAULT_TOP_OF_BOOK_INTERVAL_MS,
+            interval_ms => interval_ms,
+        };
+        let symbol = self.state.symbol.clone();
+        let session = self.state.session.clone();
+        let (sender, receiver) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms as u64));
+            let mut last_sent = None;
+
+            loop {
+                ticker.tick().await;
+                let top = match session.order_book.get_book(1).await {
+                    Ok(book) => proto::TopOfBook {
+                        symbol: symbol.clone(),
+                        bid: top_level(&book.bids),
+                        ask: top_level(&book.asks),
+                    },
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        break;
+                    }
+                };
+
+                if last_sent.as_ref() == Some(&top) {
+                    continue;
+                }
+                if sender.send(Ok(top.clone())).await.is_err() {
+                    break;
+                }
+                last_sent = Some(top);
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn place_routed_order(
+        &self,
+        request: Request<proto::PlaceOrderRequest>,
+    ) -> Result<Response<proto::OrderReport>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        self.state.authorize_orders(authorization)?;
+
+        let request = request.into_inner();
+        if !request.dry_run && !self.state.session.environment.allows_live_orders() {
+            return Err(Status::permission_denied("Live trading is disabled in config"));
+        }
+
+        let order = order_request(request.order.ok_or_else(|| Status::invalid_argument("order is required"))?)?;
+        self.state.check_symbol(&order.symbol)?;
+
+        let report = router::route_order(&self.state.session, order, request.dry_run).await?;
+        Ok(Response::new(report.into()))
+    }
+
+    type StreamFillsStream = ResponseStream<proto::Fill>;
+
+    async fn stream_fills(
+        &self,
+        _request: Request<proto::FillsRequest>,
+    ) -> Result<Response<Self::StreamFillsStream>, Status> {
+        let mut fills = self.state.session.fills.subscribe();
+        let (sender, receiver) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                match fills.recv().await {
+                    Ok(fill) => {
+                        if sender.send(Ok(fill.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Fill stream client lagged");
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+/// Serves the gRPC interface until Ctrl+C.
+pub async fn serve(state: ApiState, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+    info!(%addr, "gRPC API listening");
+    Server::builder()
+        .add_service(BlockFindersServer::new(GrpcService { state }))
+        .serve_with_shutdown(addr, async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+}
@@ -0,0 +1,207 @@
+use std::{env, sync::Arc};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use crate::{
+    analytics::LiquidityReport,
+    errors::OrderBookError,
+    metrics::metrics,
+    router::{self, OrderReport},
+    session::Session,
+    types::{BookSnapshot, Instrument, OrderRequest, PriceResponse},
+};
+
+const DEFAULT_BOOK_DEPTH: usize = 10;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub session: Arc<Session>,
+    pub symbol: String,
+    /// Bearer token required by `POST /orders`; order entry is disabled when unset.
+    pub orders_token: Option<String>,
+    /// Latest liquidity report, `None` until the first one is published.
+    pub analytics: watch::Receiver<Option<Arc<LiquidityReport>>>,
+}
+
+impl ApiState {
+    pub fn new(session: Arc<Session>, analytics: watch::Receiver<Option<Arc<LiquidityReport>>>) -> Self {
+        Self {
+            symbol: session.instrument.to_string(),
+            session,
+            orders_token: env::var("ORDERS_API_TOKEN").ok().filter(|token| !token.is_empty()),
+            analytics,
+        }
+    }
+
+    /// Checks an `Authorization` header value against the order entry token.
+    pub fn authorize_orders(&self, authorization: Option<&str>) -> Result<(), ApiError> {
+        let expected = self
+            .orders_token
+            .as_deref()
+            .ok_or_else(|| ApiError::Forbidden("Order entry is disabled; set ORDERS_API_TOKEN".to_string()))?;
+
+        let provided = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        if provided != Some(expected) {
+            return Err(ApiError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// Accepts any spelling of the session's instrument, e.g. `sol`, `SOL/USD` or `SOL-USD`.
+    pub fn check_symbol(&self, symbol: &str) -> Result<(), ApiError> {
+        if symbol.parse::<Instrument>().is_ok_and(|instrument| instrument == self.session.instrument) {
+            Ok(())
+        } else {
+            Err(ApiError::NotFound(format!("No book for symbol {}", symbol)))
+        }
+    }
+}
+
+pub enum ApiError {
+    NotFound(String),
+    Unauthorized,
+    Forbidden(String),
+    Unavailable(String),
+    OrderBook(OrderBookError),
+}
+
+impl From<OrderBookError> for ApiError {
+    fn from(e: OrderBookError) -> Self {
+        ApiError::OrderBook(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "Missing or invalid bearer token".to_string()),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            ApiError::Unavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message),
+            ApiError::OrderBook(e) => {
+                let status = match e {
+                    OrderBookError::EmptyOrderBook | OrderBookError::InsufficientVolume(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                (status, e.to_string())
+            }
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct DepthQuery {
+    depth: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct BookResponse {
+    symbol: String,
+    #[serde(flatten)]
+    book: BookSnapshot,
+}
+
+#[derive(Serialize)]
+struct VenueHealth {
+    exchange: String,
+    connected: bool,
+    taker_fee: f64,
+}
+
+#[derive(Deserialize)]
+struct PlaceOrderRequest {
+    #[serde(flatten)]
+    order: OrderRequest,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+pub fn routes(state: ApiState) -> Router {
+    Router::new()
+        .route("/book/{symbol}", get(get_book))
+        .route("/quote", post(post_quote))
+        .route("/analytics/{symbol}", get(get_analytics))
+        .route("/venues", get(get_venues))
+        .route("/orders", post(post_order))
+        .route("/metrics", get(get_metrics))
+        .with_state(state)
+}
+
+async fn get_book(
+    State(state): State<ApiState>,
+    Path(symbol): Path<String>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<BookResponse>, ApiError> {
+    state.check_symbol(&symbol)?;
+    let depth = query.depth.unwrap_or(DEFAULT_BOOK_DEPTH);
+    let book = state.session.order_book.get_book(depth).await?;
+    Ok(Json(BookResponse { symbol: state.symbol.clone(), book }))
+}
+
+async fn post_quote(
+    State(state): State<ApiState>,
+    Json(request): Json<OrderRequest>,
+) -> Result<Json<PriceResponse>, ApiError> {
+    state.check_symbol(&request.symbol)?;
+    let quote = state.session.order_book.get_quote(request).await?;
+    Ok(Json(quote))
+}
+
+async fn get_analytics(
+    State(state): State<ApiState>,
+    Path(symbol): Path<String>,
+) -> Result<Json<LiquidityReport>, ApiError> {
+    state.check_symbol(&symbol)?;
+    let report = state.analytics.borrow().clone();
+    report
+        .map(|report| Json(LiquidityReport::clone(&report)))
+        .ok_or_else(|| ApiError::Unavailable("No liquidity report yet; the book needs both sides".to_string()))
+}
+
+async fn get_venues(State(state): State<ApiState>) -> Json<Vec<VenueHealth>> {
+    let venues = state
+        .session
+        .exchanges
+        .iter()
+        .map(|exchange| VenueHealth {
+            exchange: exchange.name().to_string(),
+            connected: exchange.is_connected(),
+            taker_fee: exchange.taker_fee(),
+        })
+        .collect();
+    Json(venues)
+}
+
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(),
+    )
+}
+
+async fn post_order(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(request): Json<PlaceOrderRequest>,
+) -> Result<Json<OrderReport>, ApiError> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    state.authorize_orders(authorization)?;
+
+    if !request.dry_run && !state.session.environment.allows_live_orders() {
+        return Err(ApiError::Forbidden("Live trading is disabled in config".to_string()));
+    }
+
+    state.check_symbol(&request.order.symbol)?;
+    let report = router::route_order(&state.session, request.order, request.dry_run).await?;
+    Ok(Json(report))
+}
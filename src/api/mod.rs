@@ -0,0 +1,20 @@
+pub mod grpc;
+pub mod http;
+pub mod ws;
+
+use std::net::SocketAddr;
+use tracing::info;
+use crate::api::http::ApiState;
+
+/// Serves the HTTP endpoints and the `/ws` feed on one listener until Ctrl+C.
+pub async fn serve(state: ApiState, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!(%addr, "API listening");
+
+    let app = http::routes(state.clone()).merge(ws::routes(state));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+}
@@ -0,0 +1,113 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+use crate::{
+    api::http::ApiState,
+    types::{LevelUpdate, OrderSide, SequencedBookSnapshot},
+};
+
+/// Frames sent to feed clients: one full snapshot on connect (and again after
+/// falling behind), then every level change with a sequence number above it.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FeedMessage {
+    Snapshot {
+        symbol: String,
+        #[serde(flatten)]
+        book: SequencedBookSnapshot,
+    },
+    Update(LevelUpdate),
+}
+
+pub fn routes(state: ApiState) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(state)
+}
+
+async fn upgrade(ws: WebSocketUpgrade, State(state): State<ApiState>) -> Response {
+    ws.on_upgrade(move |socket| feed(socket, state))
+}
+
+async fn send(socket: &mut WebSocket, message: &FeedMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(text) => socket.send(Message::Text(text.into())).await.is_ok(),
+        Err(e) => {
+            error!(error = %e, error_kind = "serialize", "Failed to serialize feed message");
+            false
+        }
+    }
+}
+
+/// Sends a fresh snapshot and returns the (bid, ask) sequences it reflects.
+async fn send_snapshot(socket: &mut WebSocket, state: &ApiState) -> Option<(u64, u64)> {
+    let book = match state.session.order_book.get_sequenced_book().await {
+        Ok(book) => book,
+        Err(e) => {
+            error!(error = %e, error_kind = "order_book", "Failed to snapshot order book for feed");
+            return None;
+        }
+    };
+
+    let sequences = (book.bid_sequence, book.ask_sequence);
+    let message = FeedMessage::Snapshot {
+        symbol: state.symbol.clone(),
+        book,
+    };
+    send(socket, &message).await.then_some(sequences)
+}
+
+async fn feed(mut socket: WebSocket, state: ApiState) {
+    // Subscribe before snapshotting so no update falls between the two.
+    let mut updates = state.session.order_book.subscribe_updates();
+    let Some((mut bid_sequence, mut ask_sequence)) = send_snapshot(&mut socket, &state).await else {
+        return;
+    };
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    let last_sequence = match update.side {
+                        OrderSide::Buy => &mut bid_sequence,
+                        OrderSide::Sell => &mut ask_sequence,
+                    };
+                    if update.sequence <= *last_sequence {
+                        continue;
+                    }
+                    *last_sequence = update.sequence;
+
+                    if !send(&mut socket, &FeedMessage::Update(update)).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Feed client lagged, resending snapshot");
+                    updates = updates.resubscribe();
+                    match send_snapshot(&mut socket, &state).await {
+                        Some((bid, ask)) => {
+                            bid_sequence = bid;
+                            ask_sequence = ask;
+                        }
+                        None => break,
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
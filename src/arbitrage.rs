@@ -0,0 +1,45 @@
+use serde::Serialize;
+use crate::types::VenueQuote;
+
+/// A crossed market between two venues: buy on one venue's ask and sell
+/// into another venue's bid for a profit before fees.
+#[derive(Debug, Serialize, Clone)]
+pub struct ArbOpportunity {
+    pub buy_exchange: String,
+    pub sell_exchange: String,
+    pub buy_price: f64,
+    pub sell_price: f64,
+    pub volume: f64,
+    pub spread_bps: f64,
+}
+
+/// Compares every venue's best bid against every other venue's best ask and
+/// returns the crossings at least `min_spread_bps` wide, widest first.
+pub fn find_opportunities(bids: &[VenueQuote], asks: &[VenueQuote], min_spread_bps: f64) -> Vec<ArbOpportunity> {
+    let mut opportunities = Vec::new();
+
+    for bid in bids {
+        for ask in asks {
+            if bid.exchange == ask.exchange || bid.price <= ask.price {
+                continue;
+            }
+
+            let spread_bps = (bid.price - ask.price) / ask.price * 10_000.0;
+            if spread_bps < min_spread_bps {
+                continue;
+            }
+
+            opportunities.push(ArbOpportunity {
+                buy_exchange: ask.exchange.clone(),
+                sell_exchange: bid.exchange.clone(),
+                buy_price: ask.price,
+                sell_price: bid.price,
+                volume: bid.volume.min(ask.volume),
+                spread_bps,
+            });
+        }
+    }
+
+    opportunities.sort_by(|a, b| b.spread_bps.total_cmp(&a.spread_bps));
+    opportunities
+}
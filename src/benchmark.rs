@@ -1,70 +1,209 @@
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::order_book::UnifiedOrderBook;
-    use crate::types::{OBOrder, OrderSide};
-    use tokio::sync::mpsc::unbounded_channel;
-    use std::sync::Arc;
-    use std::time::Instant;
-    use futures::future::join_all;
-    use rand::Rng;  
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use crate::channel::{book_channel, OverloadPolicy};
+use crate::config;
+use crate::order_book::UnifiedOrderBook;
+use crate::types::{OBOrder, OrderRequest, OrderSide, VolumeUnit};
 
-    #[tokio::test]
-    async fn benchmark_process_1m_orders_from_10_tasks() {
-        let (sender, receiver) = unbounded_channel::<OBOrder>();
-        let order_book = Arc::new(UnifiedOrderBook::new(receiver));
-        let order_book_clone = Arc::clone(&order_book);
+/// Venues the synthetic updates are attributed to.
+const VENUES: [&str; 6] = ["Kraken", "Bybit", "Alpaca", "Coinbase", "Binance", "OKX"];
+/// Fixed so every run replays the same updates and results compare across commits.
+const SEED: u64 = 0xB10C;
+/// Volume on every level of the quote benchmark's book, one unit in book terms.
+const LEVEL_VOLUME: u64 = 1_000_000;
+const MID_PRICE: u64 = 10_000;
 
-        // Start the order book processing
-        tokio::spawn(async move {
-            order_book_clone.run().await;
-        });
+/// What `bench` measures and how hard.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Updates pushed through the book for the throughput and latency runs.
+    pub updates: usize,
+    /// Levels per side a quote walks, one latency run per entry.
+    pub quote_depths: Vec<usize>,
+    /// Quotes timed at each depth.
+    pub quotes: usize,
+    pub channel_capacity: usize,
+}
 
-        // Give the order book a moment to start
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+/// One measurement, in the `{name, unit, value}` shape that benchmark
+/// trackers such as github-action-benchmark ingest.
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub name: String,
+    pub unit: &'static str,
+    pub value: f64,
+}
 
-        let total_orders = 1_000_000usize;
-        let num_tasks = 10;
-        let orders_per_task = total_orders / num_tasks;
+impl BenchResult {
+    fn new(name: impl Into<String>, unit: &'static str, value: f64) -> Self {
+        Self { name: name.into(), unit, value }
+    }
+}
 
-        let start = Instant::now();
+pub async fn run(options: &BenchOptions) -> Vec<BenchResult> {
+    let mut results = bench_updates(options).await;
+    for &depth in &options.quote_depths {
+        results.extend(bench_quotes(depth.max(1), options.quotes.max(1)).await);
+    }
+    results
+}
+
+/// Feeds `options.updates` random updates through a fresh book and times
+/// each from the moment it is sent until a published snapshot includes it.
+/// Updates are sent as fast as the channel takes them, so latency includes
+/// the time spent queued behind a saturated book.
+async fn bench_updates(options: &BenchOptions) -> Vec<BenchResult> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let updates: Vec<OBOrder> = (0..options.updates)
+        .map(|_| {
+            let exchange = VENUES[rng.gen_range(0..VENUES.len())].to_string();
+            let (side, price) = if rng.gen_bool(0.5) {
+                (OrderSide::Buy, MID_PRICE - rng.gen_range(1..=500))
+            } else {
+                (OrderSide::Sell, MID_PRICE + rng.gen_range(1..=500))
+            };
+            // Roughly one update in five removes its level, as on a live feed.
+            let volume = if rng.gen_bool(0.2) { 0 } else { rng.gen_range(100..=10_000_000) };
+            OBOrder::new(exchange, side, volume, price)
+        })
+        .collect();
+    let mut sent = Vec::with_capacity(updates.len());
+    let total = updates.len() as u64;
+
+    let memory_before = resident_memory();
+    let (sender, receiver) = book_channel("bench", options.channel_capacity, OverloadPolicy::Block);
+    let order_book = Arc::new(UnifiedOrderBook::new(receiver, config::ORDER_BOOK_DEPTH));
 
-        let mut handles = Vec::with_capacity(num_tasks);
-
-        for _ in 0..num_tasks {
-            let sender_clone = sender.clone();
-            handles.push(tokio::spawn(async move {
-                let mut rng = rand::thread_rng();
-                for _ in 0..orders_per_task {
-                    let side = if rng.gen_bool(0.5) {
-                        OrderSide::Buy
-                    } else {
-                        OrderSide::Sell
-                    };
-                    let price = rng.gen_range(950..=1050);      
-                    let volume = rng.gen_range(100..=10_000);    
-
-                    let order = OBOrder {
-                        exchange: "TestExchange".to_string(),
-                        side,
-                        price,
-                        volume,
-                    };
-
-                    if sender_clone.send(order).is_err() {
-                        break;
-                    }
+    let dispatcher = order_book.clone();
+    tokio::spawn(async move { dispatcher.run().await });
+
+    let observer = order_book.clone();
+    let observations = tokio::spawn(async move {
+        let mut observations = Vec::new();
+        let mut applied = 0;
+        while applied < total {
+            match observer.next_applied(applied).await {
+                Some(count) => {
+                    applied = count;
+                    observations.push((count, Instant::now()));
                 }
-            }));
+                None => break,
+            }
+        }
+        observations
+    });
+
+    let start = Instant::now();
+    for update in updates {
+        sent.push(Instant::now());
+        if sender.send(update).await.is_err() {
+            break;
         }
+    }
+    drop(sender);
+    let observations = observations.await.unwrap_or_default();
+    let elapsed = observations.last().map_or(start.elapsed(), |&(_, at)| at - start);
+    let memory_after = resident_memory();
+
+    // Every update below a published count was applied by the time it was seen.
+    let mut latencies = Vec::with_capacity(sent.len());
+    let mut next = 0;
+    for (applied, at) in observations {
+        while next < (applied as usize).min(sent.len()) {
+            latencies.push(at - sent[next]);
+            next += 1;
+        }
+    }
+    latencies.sort_unstable();
 
-        join_all(handles).await;
+    let mut results = vec![BenchResult::new(
+        "update_throughput",
+        "updates/s",
+        latencies.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+    )];
+    results.extend(percentiles("update_latency", &latencies));
+    if let (Some(before), Some(after)) = (memory_before, memory_after) {
+        results.push(BenchResult::new("book_memory", "bytes", after.saturating_sub(before) as f64));
+    }
+    results
+}
+
+/// Builds a book where every venue shows `depth` levels per side, then times
+/// buys sized to sweep all of them.
+async fn bench_quotes(depth: usize, quotes: usize) -> Vec<BenchResult> {
+    let (sender, receiver) = book_channel("bench", depth * VENUES.len() * 2, OverloadPolicy::Block);
+    let order_book = UnifiedOrderBook::new(receiver, depth);
+
+    for level in 1..=depth as u64 {
+        for venue in VENUES {
+            for (side, price) in [(OrderSide::Buy, MID_PRICE - level), (OrderSide::Sell, MID_PRICE + level)] {
+                let _ = sender.send(OBOrder::new(venue.to_string(), side, LEVEL_VOLUME, price)).await;
+            }
+        }
+    }
+    drop(sender);
+    order_book.run().await;
+    order_book.settle().await;
 
-        let duration = start.elapsed();
-        println!("Processed 1,000,000 orders in {:?}", duration);
+    let volume = (depth * VENUES.len()) as f64 * LEVEL_VOLUME as f64 / 1_000_000.0;
+    let mut latencies = Vec::with_capacity(quotes);
+    for _ in 0..quotes {
+        let request = OrderRequest {
+            symbol: config::TICKER.to_string(),
+            side: OrderSide::Buy,
+            volume,
+            unit: VolumeUnit::Base,
+            detailed: false,
+        };
+        let start = Instant::now();
+        let quote = order_book.get_quote(request).await;
+        latencies.push(start.elapsed());
+        if quote.is_err() {
+            break;
+        }
+    }
+    latencies.sort_unstable();
+    percentiles(&format!("quote_latency_depth_{}", depth), &latencies)
+}
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+/// p50, p99 and p999 of `sorted`, in microseconds.
+fn percentiles(name: &str, sorted: &[Duration]) -> Vec<BenchResult> {
+    if sorted.is_empty() {
+        return Vec::new();
     }
+    [("p50", 0.5), ("p99", 0.99), ("p999", 0.999)]
+        .into_iter()
+        .map(|(label, quantile)| {
+            let rank = ((sorted.len() as f64 * quantile).ceil() as usize).clamp(1, sorted.len());
+            BenchResult::new(format!("{}_{}", name, label), "us", sorted[rank - 1].as_secs_f64() * 1e6)
+        })
+        .collect()
 }
 
+fn resident_memory() -> Option<usize> {
+    memory_stats::memory_stats().map(|stats| stats.physical_mem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[tokio::test]
+    async fn reports_every_measurement() {
+        let options = BenchOptions {
+            updates: 10_000,
+            quote_depths: vec![1, 10],
+            quotes: 100,
+            channel_capacity: 1024,
+        };
+        let results = run(&options).await;
+        let names: Vec<&str> = results.iter().map(|result| result.name.as_str()).collect();
+
+        for name in ["update_throughput", "update_latency_p50", "update_latency_p999", "quote_latency_depth_10_p99"] {
+            assert!(names.contains(&name), "missing {}", name);
+        }
+        assert!(results.iter().all(|result| result.value >= 0.0));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use clap::ValueEnum;
+use tokio::sync::Notify;
+use crate::errors::ChannelError;
+use crate::metrics::metrics;
+use crate::types::{OBOrder, OrderSide};
+
+/// What a book channel does when a sender finds it full.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Wait for the book to catch up, slowing the feeds down
+    #[default]
+    Block,
+    /// Discard the oldest queued update and resync the venue it came from
+    DropOldest,
+    /// Overwrite an update already queued for the same venue and price level,
+    /// waiting only when the level is not queued yet
+    Coalesce,
+}
+
+/// Identifies a venue's level, so coalescing can merge updates to it.
+type LevelKey = (String, bool, u64);
+
+fn level_key(order: &OBOrder) -> LevelKey {
+    (order.exchange.clone(), matches!(order.side, OrderSide::Buy), order.price)
+}
+
+struct State {
+    queue: VecDeque<OBOrder>,
+    /// Sequence number of `queue[0]`; an update's position is its sequence minus this.
+    head: u64,
+    /// Sequence of the queued update for each level, kept in `Coalesce` mode only.
+    queued_levels: HashMap<LevelKey, u64>,
+    /// Venues that lost updates and have not been resynced yet.
+    resync: BTreeSet<String>,
+}
+
+struct Shared {
+    name: &'static str,
+    capacity: usize,
+    policy: OverloadPolicy,
+    state: Mutex<State>,
+    readable: Notify,
+    writable: Notify,
+    resync_requested: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("book channel poisoned")
+    }
+
+    fn record_depth(&self, depth: usize) {
+        metrics().channel_backlog.with_label_values(&[self.name]).set(depth as i64);
+    }
+}
+
+/// Creates a bounded channel of book updates named `name` in the
+/// `channel_backlog` gauge, which applies `policy` once `capacity` updates
+/// are waiting.
+pub fn book_channel(name: &'static str, capacity: usize, policy: OverloadPolicy) -> (BookSender, BookReceiver) {
+    let shared = Arc::new(Shared {
+        name,
+        capacity: capacity.max(1),
+        policy,
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            head: 0,
+            queued_levels: HashMap::new(),
+            resync: BTreeSet::new(),
+        }),
+        readable: Notify::new(),
+        writable: Notify::new(),
+        resync_requested: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (BookSender { shared: shared.clone() }, BookReceiver { shared })
+}
+
+pub struct BookSender {
+    shared: Arc<Shared>,
+}
+
+impl BookSender {
+    /// Queues `order`, applying the overload policy if the channel is full.
+    pub async fn send(&self, order: OBOrder) -> Result<(), ChannelError> {
+        let shared = &self.shared;
+        let metrics = metrics();
+        loop {
+            if !shared.receiver_alive.load(Ordering::SeqCst) {
+                return Err(ChannelError::Closed);
+            }
+
+            {
+                let mut state = shared.lock();
+                let full = state.queue.len() >= shared.capacity;
+
+                if full {
+                    match shared.policy {
+                        OverloadPolicy::Block => {}
+                        OverloadPolicy::DropOldest => {
+                            if let Some(dropped) = state.queue.pop_front() {
+                                state.head += 1;
+                                metrics.channel_dropped.with_label_values(&[shared.name]).inc();
+                                if state.resync.insert(dropped.exchange) {
+                                    shared.resync_requested.notify_one();
+                                }
+                            }
+                        }
+                        OverloadPolicy::Coalesce => {
+                            if let Some(&sequence) = state.queued_levels.get(&level_key(&order)) {
+                                let position = (sequence - state.head) as usize;
+                                state.queue[position].volume = order.volume;
+                                metrics.channel_coalesced.with_label_values(&[shared.name]).inc();
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+
+                if state.queue.len() < shared.capacity {
+                    if shared.policy == OverloadPolicy::Coalesce {
+                        let sequence = state.head + state.queue.len() as u64;
+                        state.queued_levels.insert(level_key(&order), sequence);
+                    }
+                    state.queue.push_back(order);
+                    shared.record_depth(state.queue.len());
+                    drop(state);
+                    shared.readable.notify_one();
+                    return Ok(());
+                }
+            }
+
+            shared.writable.notified().await;
+        }
+    }
+
+    /// Waits until an update was dropped, then returns every venue that lost
+    /// updates since the last call. Only `DropOldest` channels ever drop.
+    pub async fn resync_requests(&self) -> Vec<String> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+                if !state.resync.is_empty() {
+                    return std::mem::take(&mut state.resync).into_iter().collect();
+                }
+            }
+            self.shared.resync_requested.notified().await;
+        }
+    }
+}
+
+impl Clone for BookSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for BookSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+pub struct BookReceiver {
+    shared: Arc<Shared>,
+}
+
+impl BookReceiver {
+    /// Next update, or `None` once every sender is gone and the queue is empty.
+    pub async fn recv(&mut self) -> Option<OBOrder> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut state = shared.lock();
+                if let Some(order) = state.queue.pop_front() {
+                    if shared.policy == OverloadPolicy::Coalesce {
+                        let head = state.head;
+                        let key = level_key(&order);
+                        if state.queued_levels.get(&key) == Some(&head) {
+                            state.queued_levels.remove(&key);
+                        }
+                    }
+                    state.head += 1;
+                    shared.record_depth(state.queue.len());
+                    drop(state);
+                    shared.writable.notify_one();
+                    return Some(order);
+                }
+                if shared.senders.load(Ordering::SeqCst) == 0 {
+                    return None;
+                }
+            }
+            shared.readable.notified().await;
+        }
+    }
+}
+
+impl Drop for BookReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::SeqCst);
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(exchange: &str, price: u64, volume: u64) -> OBOrder {
+        OBOrder::new(exchange.to_string(), OrderSide::Buy, volume, price)
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_newest_and_requests_resync() {
+        let (sender, mut receiver) = book_channel("test_drop", 2, OverloadPolicy::DropOldest);
+        sender.send(order("Kraken", 100, 1)).await.unwrap();
+        sender.send(order("Bybit", 101, 1)).await.unwrap();
+        sender.send(order("Bybit", 102, 1)).await.unwrap();
+
+        assert_eq!(sender.resync_requests().await, vec!["Kraken".to_string()]);
+        assert_eq!(receiver.recv().await.unwrap().price, 101);
+        assert_eq!(receiver.recv().await.unwrap().price, 102);
+    }
+
+    #[tokio::test]
+    async fn coalesce_overwrites_queued_level_when_full() {
+        let (sender, mut receiver) = book_channel("test_coalesce", 2, OverloadPolicy::Coalesce);
+        sender.send(order("Kraken", 100, 1)).await.unwrap();
+        sender.send(order("Kraken", 101, 1)).await.unwrap();
+        sender.send(order("Kraken", 100, 0)).await.unwrap();
+
+        let first = receiver.recv().await.unwrap();
+        assert_eq!((first.price, first.volume), (100, 0));
+        // Once the original slot is consumed, the level queues afresh.
+        sender.send(order("Kraken", 100, 5)).await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().price, 101);
+        assert_eq!(receiver.recv().await.unwrap().volume, 5);
+
+        drop(sender);
+        assert!(receiver.recv().await.is_none());
+    }
+}
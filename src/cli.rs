@@ -0,0 +1,551 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use tokio::{signal, sync::broadcast};
+use tracing::{error, info};
+use crate::{
+    analytics::{self, AnalyticsOptions, LiquidityReport},
+    api::{self, grpc, http::ApiState},
+    arbitrage,
+    benchmark::{self, BenchOptions, BenchResult},
+    channel::{book_channel, OverloadPolicy},
+    config,
+    exchanges::exchange::Environment,
+    order_book::UnifiedOrderBook,
+    replay,
+    router::{self, OrderReport},
+    session::{Session, SessionOptions},
+    telemetry::LogFormat,
+    types::{BookSnapshot, Instrument, OrderRequest, OrderSide, PriceResponse, VolumeUnit},
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "blockfinders", about = "Consolidated crypto order book across Kraken, Bybit, Alpaca, Coinbase, Binance and OKX")]
+pub struct Cli {
+    /// Print one JSON document per line instead of human-readable text
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Log output format, written to stderr
+    #[arg(long, global = true, value_enum, env = "LOG_FORMAT", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Venue endpoints and credentials to use; sandbox streams only venues with a
+    /// testnet feed, and its orders skip the live trading check
+    #[arg(long, global = true, value_enum, env = "BLOCKFINDERS_ENV", default_value_t = Environment::Production)]
+    pub environment: Environment,
+
+    /// Also merge venues quoting in these currencies, e.g. USDT into a SOL/USD book
+    #[arg(long = "bridge", global = true, env = "BLOCKFINDERS_BRIDGE", value_delimiter = ',')]
+    pub bridged_quotes: Vec<String>,
+
+    /// Book updates buffered between the venues and the book
+    #[arg(long, global = true, env = "BLOCKFINDERS_CHANNEL_CAPACITY", default_value_t = config::BOOK_CHANNEL_CAPACITY)]
+    pub channel_capacity: usize,
+
+    /// What to do when the book falls behind the venues and the buffer fills up
+    #[arg(long, global = true, value_enum, env = "BLOCKFINDERS_OVERLOAD_POLICY", default_value_t = OverloadPolicy::Block)]
+    pub overload_policy: OverloadPolicy,
+
+    /// Price levels per side kept from each venue; worse levels are dropped
+    #[arg(long, global = true, env = "BLOCKFINDERS_MAX_DEPTH", default_value_t = config::ORDER_BOOK_DEPTH)]
+    pub max_depth: usize,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Watch the consolidated book
+    Stream {
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        /// Number of price levels to show per side
+        #[arg(long, default_value_t = 5)]
+        depth: usize,
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+        /// Also record every book update to this file for `replay`
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Print public trades from every venue with a trade stream
+    Trades {
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+    },
+    /// Print a one-shot VWAP quote
+    Quote {
+        #[arg(long, value_enum)]
+        side: Side,
+        #[arg(long)]
+        qty: f64,
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        /// Read --qty as an amount of the quote currency rather than the base
+        #[arg(long)]
+        notional: bool,
+        /// Also list every venue and price level the quote fills from
+        #[arg(long)]
+        detailed: bool,
+        /// Seconds to let the book fill before quoting
+        #[arg(long, default_value_t = 10)]
+        warmup: u64,
+    },
+    /// Quote and route an order across venues
+    Order {
+        #[arg(long, value_enum)]
+        side: Side,
+        #[arg(long)]
+        qty: f64,
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        /// Read --qty as an amount of the quote currency rather than the base
+        #[arg(long)]
+        notional: bool,
+        /// Print the routing plan without sending any orders
+        #[arg(long)]
+        dry_run: bool,
+        /// Seconds to let the book fill before routing
+        #[arg(long, default_value_t = 10)]
+        warmup: u64,
+    },
+    /// Watch for crossed markets between venues
+    Arb {
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        /// Minimum spread, in basis points, worth reporting
+        #[arg(long, default_value_t = 0.0)]
+        min_bps: f64,
+        /// Seconds between scans
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+    /// Report depth near mid, book imbalance, price impact and what
+    /// aggregating venues saves over trading on one
+    Analytics {
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        /// Distances from mid, in basis points, to total depth within
+        #[arg(long, value_delimiter = ',', default_values_t = config::ANALYTICS_BANDS_BPS)]
+        bands_bps: Vec<f64>,
+        /// Order sizes, in base units, to price impact and venue savings at
+        #[arg(long, value_delimiter = ',', default_values_t = config::ANALYTICS_SIZES)]
+        sizes: Vec<f64>,
+        /// Seconds between reports
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Serve the unified book over HTTP and a WebSocket feed
+    Serve {
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Also serve the gRPC interface on this address
+        #[arg(long)]
+        grpc_addr: Option<SocketAddr>,
+        /// Seconds between liquidity reports served at `/analytics`
+        #[arg(long, default_value_t = 5)]
+        analytics_interval: u64,
+    },
+    /// Measure book throughput, update latency, quote latency and memory
+    /// against synthetic updates
+    Bench {
+        /// Updates pushed through the book
+        #[arg(long, default_value_t = 1_000_000)]
+        updates: usize,
+        /// Levels per side a quote sweeps, one quote run per depth
+        #[arg(long, value_delimiter = ',', default_values_t = [1, 10, 100, 1000])]
+        quote_depths: Vec<usize>,
+        /// Quotes timed at each depth
+        #[arg(long, default_value_t = 10_000)]
+        quotes: usize,
+    },
+    /// Replay a recording made with `stream --record`
+    Replay {
+        file: PathBuf,
+        /// Playback speed multiplier; 0 replays as fast as possible
+        #[arg(long, default_value_t = 0.0)]
+        speed: f64,
+        /// Number of price levels to show per side once the replay finishes
+        #[arg(long, default_value_t = 5)]
+        depth: usize,
+        /// Quote this side against the replayed book
+        #[arg(long, value_enum, requires = "qty")]
+        side: Option<Side>,
+        #[arg(long)]
+        qty: Option<f64>,
+        #[arg(long, default_value = config::TICKER)]
+        symbol: Instrument,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl From<Side> for OrderSide {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => OrderSide::Buy,
+            Side::Sell => OrderSide::Sell,
+        }
+    }
+}
+
+pub async fn run(cli: Cli) {
+    let json = cli.json;
+    let options = SessionOptions {
+        environment: cli.environment,
+        bridged_quotes: cli.bridged_quotes.iter().map(|quote| quote.to_ascii_uppercase()).collect(),
+        record: None,
+        trades: false,
+        channel_capacity: cli.channel_capacity,
+        overload_policy: cli.overload_policy,
+        max_depth: cli.max_depth,
+    };
+    match cli.command {
+        Command::Stream { symbol, depth, interval, record } => {
+            stream(json, SessionOptions { record, ..options }, &symbol, depth, interval).await
+        }
+        Command::Trades { symbol } => trades(json, SessionOptions { trades: true, ..options }, &symbol).await,
+        Command::Quote { side, qty, symbol, notional, detailed, warmup } => {
+            let request = OrderRequest { detailed, ..order_request(&symbol, side, qty, notional) };
+            quote(json, options, &symbol, request, warmup).await
+        }
+        Command::Order { side, qty, symbol, notional, dry_run, warmup } => {
+            order(json, options, &symbol, order_request(&symbol, side, qty, notional), dry_run, warmup).await
+        }
+        Command::Arb { symbol, min_bps, interval } => arb(json, options, &symbol, min_bps, interval).await,
+        Command::Analytics { symbol, bands_bps, sizes, interval } => {
+            liquidity(json, options, &symbol, AnalyticsOptions { bands_bps, sizes, ..AnalyticsOptions::default() }, interval).await
+        }
+        Command::Serve { symbol, addr, grpc_addr, analytics_interval } => {
+            serve(options, &symbol, addr, grpc_addr, analytics_interval).await
+        }
+        Command::Bench { updates, quote_depths, quotes } => {
+            bench(json, BenchOptions { updates, quote_depths, quotes, channel_capacity: options.channel_capacity }).await
+        }
+        Command::Replay { file, speed, depth, side, qty, symbol } => replay(json, &options, file, speed, depth, side.zip(qty), &symbol).await,
+    }
+}
+
+fn order_request(instrument: &Instrument, side: Side, qty: f64, notional: bool) -> OrderRequest {
+    OrderRequest {
+        symbol: instrument.to_string(),
+        side: side.into(),
+        volume: qty,
+        unit: if notional { VolumeUnit::Quote } else { VolumeUnit::Base },
+        detailed: false,
+    }
+}
+
+fn emit<T: Serialize>(json: bool, value: &T, text: impl FnOnce() -> String) {
+    if json {
+        match serde_json::to_string(value) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!(error = %e, error_kind = "serialize", "Failed to serialize output"),
+        }
+    } else {
+        println!("{}", text());
+    }
+}
+
+fn format_book(book: &BookSnapshot) -> String {
+    let mut out = String::new();
+    for level in book.asks.iter().rev() {
+        out.push_str(&format!("  ask {:>12.2} {:>14.6}\n", level.price, level.total_volume));
+    }
+    out.push_str("  ----------------------------\n");
+    for level in book.bids.iter() {
+        out.push_str(&format!("  bid {:>12.2} {:>14.6}\n", level.price, level.total_volume));
+    }
+    out.trim_end().to_string()
+}
+
+fn format_quote(quote: &PriceResponse) -> String {
+    let venues: Vec<String> = quote
+        .venue_volumes
+        .iter()
+        .map(|(exchange, volume)| format!("{} {}", exchange.to_lowercase(), volume))
+        .collect();
+    let mut out = format!(
+        "{:?} {} {}: vwap {:.4} ({})",
+        quote.side, quote.total_volume, quote.symbol, quote.vwap, venues.join(", "),
+    );
+    for (currency, rate) in &quote.conversion_rates {
+        out.push_str(&format!(" [{} rate {:.6}]", currency, rate));
+    }
+    if !quote.fully_filled {
+        out.push_str(" [partial fill]");
+    }
+    if let Some(detail) = &quote.detail {
+        out.push_str(&format!("\n  worst {:.4}", detail.worst_price));
+        if let (Some(mid), Some(slippage)) = (detail.mid_price, detail.slippage_bps) {
+            out.push_str(&format!(", mid {:.4}, slippage {:.2} bps", mid, slippage));
+        }
+        for slice in &detail.slices {
+            out.push_str(&format!("\n  {:<10} {:>12.4} {:>14.6}", slice.exchange, slice.price, slice.volume));
+        }
+    }
+    out
+}
+
+fn format_report(report: &OrderReport) -> String {
+    let mut out = format_quote(&report.quote);
+    for route in &report.routes {
+        out.push_str(&format!(
+            "\n  {} {} (fee ~{:.4}) -> {}",
+            route.exchange, route.volume, route.estimated_fee, route.status,
+        ));
+    }
+    out
+}
+
+async fn snapshot(order_book: &UnifiedOrderBook, depth: usize) -> Option<BookSnapshot> {
+    match order_book.get_book(depth).await {
+        Ok(book) => Some(book),
+        Err(e) => {
+            error!(error = %e, error_kind = "order_book", "Error reading order book");
+            None
+        }
+    }
+}
+
+async fn stream(json: bool, options: SessionOptions, instrument: &Instrument, depth: usize, interval: u64) {
+    let session = Session::start(instrument, options).await;
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Some(book) = snapshot(&session.order_book, depth).await {
+                    emit(json, &book, || format_book(&book));
+                }
+            }
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    session.shutdown().await;
+}
+
+async fn trades(json: bool, options: SessionOptions, instrument: &Instrument) {
+    let session = Session::start(instrument, options).await;
+    let mut trades = session.trades.subscribe();
+
+    loop {
+        tokio::select! {
+            trade = trades.recv() => match trade {
+                Ok(trade) => emit(json, &trade, || format!(
+                    "{} {:?} {} {} @ {:.4}",
+                    trade.exchange, trade.side, trade.size, trade.symbol, trade.price,
+                )),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    error!(skipped, error_kind = "lagged", "Trade output fell behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    session.shutdown().await;
+}
+
+async fn quote(json: bool, options: SessionOptions, instrument: &Instrument, request: OrderRequest, warmup: u64) {
+    let session = Session::start(instrument, options).await;
+    tokio::time::sleep(Duration::from_secs(warmup)).await;
+
+    match session.order_book.get_quote(request).await {
+        Ok(quote) => emit(json, &quote, || format_quote(&quote)),
+        Err(e) => error!(error = %e, error_kind = "quote", "Error getting best quote"),
+    }
+
+    session.shutdown().await;
+}
+
+async fn order(json: bool, options: SessionOptions, instrument: &Instrument, request: OrderRequest, dry_run: bool, warmup: u64) {
+    if !dry_run && !options.environment.allows_live_orders() {
+        error!("Live trading is disabled in config; rerun with --dry-run or --environment sandbox");
+        return;
+    }
+
+    let session = Session::start(instrument, options).await;
+    tokio::time::sleep(Duration::from_secs(warmup)).await;
+
+    match router::route_order(&session, request, dry_run).await {
+        Ok(report) => emit(json, &report, || format_report(&report)),
+        Err(e) => error!(error = %e, error_kind = "order", "Error routing order"),
+    }
+
+    session.shutdown().await;
+}
+
+async fn arb(json: bool, options: SessionOptions, instrument: &Instrument, min_bps: f64, interval: u64) {
+    let session = Session::start(instrument, options).await;
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let tops = match session.order_book.get_venue_tops().await {
+                    Ok(tops) => tops,
+                    Err(e) => {
+                        error!(error = %e, error_kind = "order_book", "Error reading order book");
+                        continue;
+                    }
+                };
+
+                for opportunity in arbitrage::find_opportunities(&tops.bids, &tops.asks, min_bps) {
+                    emit(json, &opportunity, || format!(
+                        "buy {} on {} @ {:.2}, sell on {} @ {:.2}: {:.2} bps",
+                        opportunity.volume, opportunity.buy_exchange, opportunity.buy_price,
+                        opportunity.sell_exchange, opportunity.sell_price, opportunity.spread_bps,
+                    ));
+                }
+            }
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    session.shutdown().await;
+}
+
+async fn liquidity(json: bool, options: SessionOptions, instrument: &Instrument, analytics_options: AnalyticsOptions, interval: u64) {
+    let session = Session::start(instrument, options).await;
+    let mut reports = analytics::spawn_publisher(
+        session.order_book.clone(),
+        AnalyticsOptions { taker_fees: session.taker_fees(), ..analytics_options },
+        Duration::from_secs(interval.max(1)),
+    );
+
+    loop {
+        tokio::select! {
+            changed = reports.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let report = reports.borrow_and_update().clone();
+                if let Some(report) = report {
+                    emit(json, &*report, || format_liquidity(&report));
+                }
+            }
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    session.shutdown().await;
+}
+
+fn format_liquidity(report: &LiquidityReport) -> String {
+    let mut out = format!("mid {:.4}, spread {:.2} bps\n", report.mid_price, report.spread_bps);
+    for band in &report.depth {
+        out.push_str(&format!(
+            "  ±{} bps: bid {:.6} ask {:.6} imbalance {:+.3}\n",
+            band.bps, band.bid_volume, band.ask_volume, band.imbalance,
+        ));
+        for venue in &band.venues {
+            out.push_str(&format!("    {:<10} bid {:.6} ask {:.6}\n", venue.exchange, venue.bid_volume, venue.ask_volume));
+        }
+    }
+    for point in &report.impact {
+        out.push_str(&format!(
+            "  {:?} {}: vwap {:.4}, impact {:.2} bps{}\n",
+            point.side, point.size, point.vwap, point.impact_bps,
+            if point.filled < point.size { format!(", only {} available", point.filled) } else { String::new() },
+        ));
+    }
+    for saving in &report.savings {
+        let versus = match (saving.venue_vwap, saving.savings_bps) {
+            (Some(venue_vwap), Some(savings_bps)) => format!("vwap {:.4}, consolidated saves {:.2} bps", venue_vwap, savings_bps),
+            _ => "cannot fill alone".to_string(),
+        };
+        out.push_str(&format!("  {:?} {} on {}: {}\n", saving.side, saving.size, saving.exchange, versus));
+    }
+    out.trim_end().to_string()
+}
+
+async fn serve(options: SessionOptions, instrument: &Instrument, addr: SocketAddr, grpc_addr: Option<SocketAddr>, analytics_interval: u64) {
+    let session = Arc::new(Session::start(instrument, options).await);
+    let analytics = analytics::spawn_publisher(
+        session.order_book.clone(),
+        AnalyticsOptions { taker_fees: session.taker_fees(), ..AnalyticsOptions::default() },
+        Duration::from_secs(analytics_interval.max(1)),
+    );
+    let state = ApiState::new(session.clone(), analytics);
+
+    let grpc = async {
+        if let Some(grpc_addr) = grpc_addr {
+            if let Err(e) = grpc::serve(state.clone(), grpc_addr).await {
+                error!(error = %e, error_kind = "server", "gRPC server failed");
+            }
+        }
+    };
+    let http = async {
+        if let Err(e) = api::serve(state.clone(), addr).await {
+            error!(error = %e, error_kind = "server", "API server failed");
+        }
+    };
+    tokio::join!(http, grpc);
+
+    session.shutdown().await;
+}
+
+async fn bench(json: bool, options: BenchOptions) {
+    let results = benchmark::run(&options).await;
+    emit(json, &results, || format_bench(&results));
+}
+
+fn format_bench(results: &[BenchResult]) -> String {
+    results
+        .iter()
+        .map(|result| format!("  {:<32} {:>16.3} {}", result.name, result.value, result.unit))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn replay(json: bool, options: &SessionOptions, file: PathBuf, speed: f64, depth: usize, quote: Option<(Side, f64)>, instrument: &Instrument) {
+    // A replay has no live feed to fall behind, so it always waits for the book.
+    let (sender, receiver) = book_channel("main", options.channel_capacity, OverloadPolicy::Block);
+    let order_book = Arc::new(UnifiedOrderBook::new(receiver, options.max_depth));
+
+    let feeder = tokio::spawn(replay::replay_file(file, speed, sender));
+
+    // The book stops once the replay drops its sender and every update has
+    // been forwarded to the side books, which may still be applying them.
+    order_book.run().await;
+    order_book.settle().await;
+
+    match feeder.await {
+        Ok(Ok(count)) => info!(count, "Replay finished"),
+        Ok(Err(e)) => {
+            error!(error = %e, error_kind = "io", "Failed to replay recording");
+            return;
+        }
+        Err(e) => {
+            error!(error = %e, error_kind = "task", "Replay task failed");
+            return;
+        }
+    }
+
+    if let Some(book) = snapshot(&order_book, depth).await {
+        emit(json, &book, || format_book(&book));
+    }
+
+    if let Some((side, qty)) = quote {
+        let request = order_request(instrument, side, qty, false);
+        match order_book.get_quote(request).await {
+            Ok(quote) => emit(json, &quote, || format_quote(&quote)),
+            Err(e) => error!(error = %e, error_kind = "quote", "Error getting best quote"),
+        }
+    }
+}
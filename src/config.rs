@@ -1,3 +1,13 @@
+/// Levels per side kept from each venue, and the depth requested from
+/// venues whose subscription takes one.
 pub const ORDER_BOOK_DEPTH: usize = 100;
+/// Book updates each internal channel holds before its overload policy applies.
+pub const BOOK_CHANNEL_CAPACITY: usize = 65_536;
+/// Distances from mid, in basis points, that liquidity analytics total depth within.
+pub const ANALYTICS_BANDS_BPS: [f64; 4] = [10.0, 25.0, 50.0, 100.0];
+/// Order sizes, in base units, along the analytics price impact curve.
+pub const ANALYTICS_SIZES: [f64; 4] = [1.0, 10.0, 100.0, 1000.0];
 pub const TICKER: &str = "SOL";
+/// Quote currency assumed when a symbol is given as a bare base, e.g. `SOL`.
+pub const QUOTE: &str = "USD";
 pub const LIVE_TRADING: bool = false;
\ No newline at end of file
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use futures_util::sink::SinkExt;
+use futures_util::stream::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tracing::{debug, error, info, info_span, warn, Instrument as _};
+use crate::channel::{book_channel, BookReceiver, OverloadPolicy};
+use crate::metrics::metrics;
+use crate::types::{OBOrder, OrderSide};
+
+/// Rates come from Kraken's ticker, which lists the major stablecoins against USD.
+const RATE_WEBSOCKET_URL: &str = "wss://ws.kraken.com/v2";
+const RATE_CURRENCY: &str = "USD";
+
+/// How long a rate stays usable without a frame from its feed. Kraken sends
+/// a heartbeat every second on a subscribed connection, so a feed this quiet
+/// is dead and its last rate can no longer be trusted.
+const MAX_RATE_AGE: Duration = Duration::from_secs(30);
+/// First wait before reconnecting a rate feed; doubles up to the maximum.
+const RATE_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RATE_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+struct TickerMessage {
+    data: Vec<Ticker>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ticker {
+    bid: f64,
+    ask: f64,
+}
+
+/// Latest rate for each bridged quote currency into the book's quote
+/// currency, with when its feed was last heard from.
+#[derive(Default)]
+struct ConversionRates {
+    rates: RwLock<BTreeMap<String, (f64, Instant)>>,
+}
+
+impl ConversionRates {
+    /// The rate for `quote`, unless its feed has been quiet for `MAX_RATE_AGE`.
+    fn get(&self, quote: &str) -> Option<f64> {
+        self.rates
+            .read()
+            .expect("conversion rates poisoned")
+            .get(quote)
+            .filter(|(_, heard)| heard.elapsed() <= MAX_RATE_AGE)
+            .map(|&(rate, _)| rate)
+    }
+
+    fn set(&self, quote: &str, rate: f64) {
+        self.rates.write().expect("conversion rates poisoned").insert(quote.to_string(), (rate, Instant::now()));
+    }
+
+    /// Keeps the current rate for `quote` fresh while its feed is alive but
+    /// the ticker has not moved.
+    fn touch(&self, quote: &str) {
+        if let Some((_, heard)) = self.rates.write().expect("conversion rates poisoned").get_mut(quote) {
+            *heard = Instant::now();
+        }
+    }
+}
+
+/// Reprices book updates from venues quoting in another currency into
+/// `target`, using a live rate per bridged currency. `venue_quotes` maps each
+/// venue to the quote currency it streams; other venues pass through as-is.
+///
+/// A level keeps the price and rate it was first converted at until the venue
+/// removes it, so later updates and removals land on the same level even after
+/// the rate has moved. Source levels that round to the same converted price
+/// are published as one level holding their summed volume. Updates for new
+/// levels are dropped while no current rate is known.
+pub fn spawn_converter(
+    target: &str,
+    venue_quotes: HashMap<String, String>,
+    capacity: usize,
+    mut receiver: BookReceiver,
+) -> BookReceiver {
+    let rates = Arc::new(ConversionRates::default());
+    // Blocking here pushes overload back to the feed channel and its policy.
+    let (sender, converted_receiver) = book_channel("converted", capacity, OverloadPolicy::Block);
+
+    let mut bridged: Vec<&String> = venue_quotes.values().filter(|quote| *quote != target).collect();
+    bridged.sort();
+    bridged.dedup();
+    for quote in bridged {
+        spawn_rate_feed(quote.clone(), target.to_string(), rates.clone());
+    }
+
+    let mut converter = Converter::new(target, venue_quotes, rates);
+    tokio::spawn(async move {
+        while let Some(order) = receiver.recv().await {
+            let Some(order) = converter.convert(order) else {
+                continue;
+            };
+            if sender.send(order).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    converted_receiver
+}
+
+/// One venue's level: venue, whether it is a bid, and price.
+type LevelKey = (String, bool, u64);
+
+/// The state `spawn_converter` keeps between updates.
+struct Converter {
+    target: String,
+    venue_quotes: HashMap<String, String>,
+    rates: Arc<ConversionRates>,
+    /// Converted price and the rate applied, per venue, side and venue price.
+    converted: HashMap<LevelKey, (u64, f64)>,
+    /// Volume and rate of every source level behind each converted venue,
+    /// side and price, keyed by venue price.
+    merged: HashMap<LevelKey, HashMap<u64, (u64, f64)>>,
+    /// Latest epoch seen per venue. A newer one means the book cleared the
+    /// venue, so none of its converted levels are left to line up with.
+    epochs: HashMap<String, u64>,
+    /// Venues already warned about dropping updates, until a rate is back.
+    missing_rate: HashSet<String>,
+}
+
+impl Converter {
+    fn new(target: &str, venue_quotes: HashMap<String, String>, rates: Arc<ConversionRates>) -> Self {
+        Self {
+            target: target.to_string(),
+            venue_quotes,
+            rates,
+            converted: HashMap::new(),
+            merged: HashMap::new(),
+            epochs: HashMap::new(),
+            missing_rate: HashSet::new(),
+        }
+    }
+
+    /// The update priced in the target currency, or `None` when it must be dropped.
+    fn convert(&mut self, mut order: OBOrder) -> Option<OBOrder> {
+        let quote = match self.venue_quotes.get(&order.exchange) {
+            Some(quote) if *quote != self.target => quote,
+            _ => return Some(order),
+        };
+
+        let epoch = self.epochs.entry(order.exchange.clone()).or_default();
+        if order.epoch < *epoch {
+            // The book drops these too; converting one could evict a level
+            // the newer connection has already sent.
+            return None;
+        }
+        if order.epoch > *epoch {
+            *epoch = order.epoch;
+            self.converted.retain(|(exchange, _, _), _| *exchange != order.exchange);
+            self.merged.retain(|(exchange, _, _), _| *exchange != order.exchange);
+        }
+
+        let key = (order.exchange.clone(), matches!(order.side, OrderSide::Buy), order.price);
+        let (price, rate) = match self.converted.get(&key) {
+            Some(&converted) => converted,
+            // The level never made it into the book, so there is nothing to remove.
+            None if order.volume == 0 => return None,
+            None => match self.rates.get(quote) {
+                Some(rate) => {
+                    if self.missing_rate.remove(&order.exchange) {
+                        info!(venue = %order.exchange, quote = %quote, "Conversion rate available again");
+                    }
+                    ((order.price as f64 * rate).round() as u64, rate)
+                }
+                None => {
+                    metrics().conversion_dropped.with_label_values(&[&order.exchange]).inc();
+                    if self.missing_rate.insert(order.exchange.clone()) {
+                        warn!(venue = %order.exchange, quote = %quote, error_kind = "conversion", "No current conversion rate, dropping new levels");
+                    }
+                    return None;
+                }
+            },
+        };
+
+        let source_price = order.price;
+        let merged_key = (key.0.clone(), key.1, price);
+        if order.volume == 0 {
+            self.converted.remove(&key);
+        } else {
+            self.converted.insert(key, (price, rate));
+        }
+
+        // The book holds one volume per venue and price, so it gets the sum
+        // of every source level behind this price, and the rate they average.
+        let sources = self.merged.entry(merged_key.clone()).or_default();
+        if order.volume == 0 {
+            sources.remove(&source_price);
+        } else {
+            sources.insert(source_price, (order.volume, rate));
+        }
+        let volume: u64 = sources.values().map(|&(volume, _)| volume).sum();
+        let weighted: f64 = sources.values().map(|&(volume, rate)| volume as f64 * rate).sum();
+        if sources.is_empty() {
+            self.merged.remove(&merged_key);
+        }
+
+        order.price = price;
+        order.volume = volume;
+        order.rate = Some(if volume > 0 { weighted / volume as f64 } else { rate });
+        Some(order)
+    }
+}
+
+/// Keeps `rates[from]` at the mid price of `from` in `to`, read from the
+/// `from/USD` or `to/USD` ticker. Pairs without a USD leg are not supported.
+/// Reconnects with backoff whenever the feed fails or goes quiet.
+fn spawn_rate_feed(from: String, to: String, rates: Arc<ConversionRates>) {
+    let (pair, inverted) = if to == RATE_CURRENCY {
+        (format!("{}/{}", from, to), false)
+    } else if from == RATE_CURRENCY {
+        (format!("{}/{}", to, from), true)
+    } else {
+        warn!(from = %from, to = %to, error_kind = "conversion", "No rate source for quote conversion");
+        return;
+    };
+
+    let span = info_span!("rate_feed", pair = %pair);
+    tokio::spawn(async move {
+        let mut delay = RATE_RECONNECT_DELAY;
+        loop {
+            if stream_rate(&pair, &from, inverted, &rates).await {
+                delay = RATE_RECONNECT_DELAY;
+            }
+            warn!(retry_in_ms = delay.as_millis() as u64, error_kind = "conversion", "Rate feed down, reconnecting");
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RATE_RECONNECT_DELAY);
+        }
+    }.instrument(span));
+}
+
+/// Streams one connection's rates for `pair` into `rates[from]` until it
+/// fails or goes quiet. Returns whether it delivered a rate.
+async fn stream_rate(pair: &str, from: &str, inverted: bool, rates: &ConversionRates) -> bool {
+    let subscribe_message = json!({
+        "method": "subscribe",
+        "params": { "channel": "ticker", "symbol": [pair] },
+    });
+
+    let mut socket = match connect_async(RATE_WEBSOCKET_URL).await {
+        Ok((socket, _)) => socket,
+        Err(e) => {
+            error!(error = %e, error_kind = "websocket", "Failed to connect rate feed");
+            return false;
+        }
+    };
+    if let Err(e) = socket.send(Message::Text(subscribe_message.to_string().into())).await {
+        error!(error = %e, error_kind = "websocket", "Failed to subscribe rate feed");
+        return false;
+    }
+    info!("Subscribed to conversion rate");
+
+    let mut delivered = false;
+    loop {
+        let result = match tokio::time::timeout(MAX_RATE_AGE, socket.next()).await {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(_) => {
+                warn!(error_kind = "conversion", "Rate feed went quiet");
+                break;
+            }
+        };
+        match result {
+            Ok(Message::Text(text)) => {
+                rates.touch(from);
+                if let Some(rate) = ticker_rate(&text, inverted) {
+                    debug!(rate, "Conversion rate updated");
+                    rates.set(from, rate);
+                    delivered = true;
+                }
+            }
+            Ok(Message::Close(_)) => {
+                info!("Rate feed closed");
+                break;
+            }
+            Err(e) => {
+                error!(error = %e, error_kind = "websocket", "Rate feed error");
+                break;
+            }
+            _ => {}
+        }
+    }
+    delivered
+}
+
+/// The mid price in a ticker frame, inverted when the pair is quoted the
+/// other way round. Other frames and one-sided tickers yield `None`.
+fn ticker_rate(text: &str, inverted: bool) -> Option<f64> {
+    let message = serde_json::from_str::<TickerMessage>(text).ok()?;
+    let ticker = message.data.iter().rev().find(|ticker| ticker.bid > 0.0 && ticker.ask > 0.0)?;
+    let mid = (ticker.bid + ticker.ask) / 2.0;
+    Some(if inverted { 1.0 / mid } else { mid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converter(rates: Arc<ConversionRates>) -> Converter {
+        let venue_quotes = HashMap::from([
+            ("Kraken".to_string(), "USD".to_string()),
+            ("Bybit".to_string(), "USDT".to_string()),
+        ]);
+        Converter::new("USD", venue_quotes, rates)
+    }
+
+    fn bybit(price: u64, volume: u64) -> OBOrder {
+        OBOrder::new("Bybit".to_string(), OrderSide::Buy, volume, price)
+    }
+
+    #[test]
+    fn levels_keep_the_rate_they_were_first_converted_at() {
+        let rates = Arc::new(ConversionRates::default());
+        rates.set("USDT", 0.5);
+        let mut converter = converter(rates.clone());
+
+        let first = converter.convert(bybit(10_000, 1_000_000)).unwrap();
+        assert_eq!((first.price, first.rate), (5_000, Some(0.5)));
+
+        rates.set("USDT", 0.25);
+        let updated = converter.convert(bybit(10_000, 2_000_000)).unwrap();
+        assert_eq!((updated.price, updated.rate), (5_000, Some(0.5)));
+        let new_level = converter.convert(bybit(30_000, 1_000_000)).unwrap();
+        assert_eq!((new_level.price, new_level.rate), (7_500, Some(0.25)));
+
+        let removed = converter.convert(bybit(10_000, 0)).unwrap();
+        assert_eq!((removed.price, removed.volume), (5_000, 0));
+        assert_eq!((converter.converted.len(), converter.merged.len()), (1, 1));
+
+        let kraken = converter.convert(OBOrder::new("Kraken".to_string(), OrderSide::Sell, 1_000_000, 10_000)).unwrap();
+        assert_eq!((kraken.price, kraken.rate), (10_000, None));
+    }
+
+    #[test]
+    fn new_levels_are_dropped_without_a_current_rate() {
+        let rates = Arc::new(ConversionRates::default());
+        let mut converter = converter(rates.clone());
+        assert!(converter.convert(bybit(10_000, 1_000_000)).is_none());
+
+        let heard = Instant::now().checked_sub(MAX_RATE_AGE + Duration::from_secs(1)).unwrap();
+        rates.rates.write().unwrap().insert("USDT".to_string(), (0.5, heard));
+        assert!(converter.convert(bybit(10_000, 1_000_000)).is_none());
+
+        rates.touch("USDT");
+        assert_eq!(converter.convert(bybit(10_000, 1_000_000)).unwrap().price, 5_000);
+    }
+
+    #[test]
+    fn removals_and_clears_free_converted_levels() {
+        let rates = Arc::new(ConversionRates::default());
+        rates.set("USDT", 0.5);
+        let mut converter = converter(rates);
+
+        // Removing a level that was never converted has nothing to remove.
+        assert!(converter.convert(bybit(10_000, 0)).is_none());
+
+        converter.convert(bybit(10_000, 1_000_000)).unwrap();
+        converter.convert(bybit(20_000, 1_000_000)).unwrap();
+        converter.convert(bybit(10_000, 0)).unwrap();
+        assert_eq!((converter.converted.len(), converter.merged.len()), (1, 1));
+
+        // A resynced connection starts over; the stale one's updates are dropped.
+        let resynced = OBOrder { epoch: 1, ..bybit(40_000, 1_000_000) };
+        converter.convert(resynced).unwrap();
+        assert_eq!((converter.converted.len(), converter.merged.len()), (1, 1));
+        assert!(converter.convert(bybit(20_000, 0)).is_none());
+    }
+
+    #[test]
+    fn source_levels_converting_to_one_price_share_it() {
+        let rates = Arc::new(ConversionRates::default());
+        rates.set("USDT", 0.5);
+        let mut converter = converter(rates.clone());
+        converter.convert(bybit(10_000, 1_000_000)).unwrap();
+
+        // 99.99 at the new rate rounds to the same 50.00 as 100.00 did.
+        rates.set("USDT", 0.50004);
+        let merged = converter.convert(bybit(9_999, 2_000_000)).unwrap();
+        assert_eq!((merged.price, merged.volume), (5_000, 3_000_000));
+        assert!((merged.rate.unwrap() - (0.5 + 2.0 * 0.50004) / 3.0).abs() < 1e-12);
+
+        // Removing one source level leaves the other's volume in place.
+        let removed = converter.convert(bybit(10_000, 0)).unwrap();
+        assert_eq!((removed.price, removed.volume), (5_000, 2_000_000));
+        let removed = converter.convert(bybit(9_999, 0)).unwrap();
+        assert_eq!((removed.price, removed.volume), (5_000, 0));
+        assert!(converter.merged.is_empty());
+    }
+
+    #[test]
+    fn ticker_rate_is_the_mid_price() {
+        let frame = r#"{"channel":"ticker","type":"update","data":[{"symbol":"USDT/USD","bid":0.9997,"ask":0.9999}]}"#;
+        assert!((ticker_rate(frame, false).unwrap() - 0.9998).abs() < 1e-12);
+        assert!((ticker_rate(frame, true).unwrap() - 1.0 / 0.9998).abs() < 1e-12);
+        assert_eq!(ticker_rate(r#"{"channel":"heartbeat"}"#, false), None);
+    }
+}
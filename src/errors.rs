@@ -20,6 +20,12 @@ pub enum ExchangeError {
 
     #[error("Serialization error: {0}")]
     SerializationError(String),
+
+    #[error("Failed to fetch instrument info: {0}")]
+    InstrumentInfo(String),
+
+    #[error("Not supported by {0}")]
+    Unsupported(String),
 }
 
 #[derive(Error, Debug)]
@@ -39,18 +45,24 @@ pub enum OrderPlaceError {
     #[error("Network error: {0}")]
     NetworkError(reqwest::Error),
 
+    #[error("Invalid quantity: {0}")]
+    InvalidQuantity(String),
+
     #[error("Other error: {0}")]
     Other(String),
 }
 
+#[derive(Error, Debug)]
+pub enum ChannelError {
+    #[error("Channel closed")]
+    Closed,
+}
+
 #[derive(Error, Debug)]
 pub enum OrderBookError {
     #[error("Failed to receive order: {0}")]
     ReceiveError(String),
 
-    #[error("Failed to process order: {0}")]
-    ProcessError(String),
-
     #[error("Order book is empty")]
     EmptyOrderBook,
 
@@ -1,28 +1,43 @@
 use crate::exchanges::exchange::Exchange;
 use crate::errors::{ExchangeError, OrderPlaceError};
-use crate::types::{OBOrder, Order, OrderSide};
-use crate::config::{
-    TICKER,
-};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::publish;
+use tracing::{debug, error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, OBOrder, Order, OrderSide, Trade};
 use serde::Serialize;
 use reqwest::Client;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio_tungstenite::connect_async;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering}
-};
+use std::sync::{Arc, Mutex};
 use serde::Deserialize;
 
+const VENUE: &str = "Alpaca";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USD", "USDT", "USDC"];
+
+/// One entry of a market data frame, which is an array of them.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "T")]
+enum AlpacaMessage {
+    #[serde(rename = "o")]
+    Book(AlpacaBookMessage),
+    #[serde(rename = "t")]
+    Trade(AlpacaTradeMessage),
+    /// Acks, errors and subscription confirmations.
+    #[serde(other)]
+    Other,
+}
+
 #[derive(Debug, Deserialize)]
 struct AlpacaBookMessage {
-    #[serde(rename = "T")]
-    msg_type: String,
-    #[serde(rename = "S")]
-    symbol: String,
     #[serde(rename = "t")]
     timestamp: String,
     #[serde(default)]
@@ -31,6 +46,18 @@ struct AlpacaBookMessage {
     a: Vec<AlpacaLevel>,
 }
 
+#[derive(Debug, Deserialize)]
+struct AlpacaTradeMessage {
+    #[serde(rename = "p")]
+    price: f64,
+    #[serde(rename = "s")]
+    size: f64,
+    #[serde(rename = "t")]
+    timestamp: String,
+    /// Taker side, `B` or `S`.
+    tks: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct AlpacaLevel {
     #[serde(rename = "p")]
@@ -39,12 +66,79 @@ struct AlpacaLevel {
     size: f64,
 }
 
+/// Reads the order book entries of a frame into the orders they carry.
+/// Trades and control messages yield none.
+fn parse_book_frame(text: &str) -> Vec<OBOrder> {
+    let metrics = metrics();
+    let messages = match serde_json::from_str::<Vec<AlpacaMessage>>(text) {
+        Ok(messages) => messages,
+        Err(e) if text.contains("\"T\":\"o\"") => {
+            metrics.feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    let mut orders = Vec::new();
+    let book_updates = messages.into_iter().filter_map(|message| match message {
+        AlpacaMessage::Book(update) => Some(update),
+        _ => None,
+    });
+    for update in book_updates {
+        metrics.observe_feed_latency_rfc3339(VENUE, &update.timestamp);
+        for (side, levels) in [(OrderSide::Buy, update.b), (OrderSide::Sell, update.a)] {
+            for level in levels {
+                orders.push(OBOrder::new(
+                    VENUE.to_string(),
+                    side.clone(),
+                    (level.size * 1_000_000.0) as u64,
+                    (level.price * 100.0) as u64,
+                ));
+            }
+        }
+    }
+    orders
+}
+
+/// Reads the trade entries of a frame into the trades they carry, labelled
+/// with `symbol`. Book updates and control messages yield none.
+fn parse_trade_frame(text: &str, symbol: &str) -> Vec<Trade> {
+    let messages = match serde_json::from_str::<Vec<AlpacaMessage>>(text) {
+        Ok(messages) => messages,
+        Err(e) if text.contains("\"T\":\"t\"") => {
+            metrics().feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse trade frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    messages
+        .into_iter()
+        .filter_map(|message| match message {
+            AlpacaMessage::Trade(trade) => Some(trade),
+            _ => None,
+        })
+        .map(|trade| Trade {
+            exchange: VENUE.to_string(),
+            symbol: symbol.to_string(),
+            side: if trade.tks == "B" { OrderSide::Buy } else { OrderSide::Sell },
+            price: trade.price,
+            size: trade.size,
+            timestamp_ms: chrono::DateTime::parse_from_rfc3339(&trade.timestamp)
+                .map_or(0, |time| time.timestamp_millis() as u64),
+        })
+        .collect()
+}
+
 
 #[derive(Serialize)]
 pub struct OrderRequest {
     symbol: String,
     qty: String,
     side: String,
+    #[serde(rename = "type")]
     type_: String,
     time_in_force: String,
 }
@@ -56,36 +150,40 @@ pub struct AlpacaExchange {
     order_url: String,
     websocket_url: String,
     client: Client,
-    active: Arc<AtomicBool>,
-    sender: UnboundedSender<OBOrder>,
-    fees: f64, 
+    feed: FeedLifecycle,
+    sender: BookSender,
+    /// Where trades go while subscribed. They arrive on the book connection,
+    /// as Alpaca only allows one market data connection per account.
+    trade_sender: Arc<Mutex<Option<UnboundedSender<Trade>>>>,
+    /// Frames to send on the live book connection.
+    outgoing: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    fees: f64,
+    instruments_url: String,
+    instruments: Arc<InstrumentRegistry>,
 }
 
 impl AlpacaExchange {
-    pub fn new(api_key: String, api_secret: String, sender: UnboundedSender<OBOrder>) -> Self {
+    pub fn new(api_key: String, api_secret: String, instruments: Arc<InstrumentRegistry>, sender: BookSender) -> Self {
+        let rest_url = "https://api.alpaca.markets";
         AlpacaExchange {
-            name: "Alpaca".to_string(),
+            name: VENUE.to_string(),
             api_key,
             api_secret,
-            order_url: "https://api.alpaca.markets/v2/orders".to_string(),
+            order_url: format!("{}/v2/orders", rest_url),
             websocket_url: "wss://stream.data.alpaca.markets/v1beta3/crypto/us".to_string(),
             client: Client::new(),
-            active: Arc::new(AtomicBool::new(false)),
-            sender: sender,
-            fees: 0.0
+            feed: FeedLifecycle::default(),
+            sender,
+            trade_sender: Arc::new(Mutex::new(None)),
+            outgoing: Mutex::new(None),
+            fees: 0.0,
+            instruments_url: format!("{}/v2/assets", rest_url),
+            instruments,
         }
     }
-}
-
-#[async_trait::async_trait]
-impl Exchange for AlpacaExchange {
-    async fn subscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError> {
-        if symbol != TICKER {
-            return Err(ExchangeError::InvalidSymbol(symbol.to_string()));
-        }
-
-        let symbol_str = format!("{}/USD", symbol);
 
+    /// Opens a market data connection and authenticates it.
+    async fn connect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, ExchangeError> {
         let auth_message = serde_json::json!({
             "action": "auth",
             "key": self.api_key,
@@ -103,7 +201,7 @@ impl Exchange for AlpacaExchange {
                     if !text.contains("success") {
                         return Err(ExchangeError::SubscriptionFailed("Authentication failed".to_string()));
                     }
-                    println!("Successfully connected");
+                    debug!(venue = %self.name, "Successfully connected");
                 }
                 Ok(Message::Close(_)) => {
                     return Err(ExchangeError::ConnectionClosed);
@@ -123,7 +221,7 @@ impl Exchange for AlpacaExchange {
                     if !text.contains("authenticated") {
                         return Err(ExchangeError::SubscriptionFailed("Authentication failed".to_string()));
                     }
-                    println!("Successfully authenticated");
+                    debug!(venue = %self.name, "Successfully authenticated");
                 }
                 Ok(Message::Close(_)) => {
                     return Err(ExchangeError::ConnectionClosed);
@@ -135,99 +233,179 @@ impl Exchange for AlpacaExchange {
             }
         }
 
-        let subscribe_message = serde_json::json!({
+        Ok(socket)
+    }
+
+    /// Sends `message` on the book connection if one is up. Without one there
+    /// is nothing to change; the next connection subscribes to what is set.
+    fn send_on_book_connection(&self, message: serde_json::Value) {
+        if let Some(outgoing) = self.outgoing.lock().expect("outgoing sender poisoned").as_ref() {
+            let _ = outgoing.send(message.to_string());
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Exchange for AlpacaExchange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}/{}", instrument.base, instrument.quote))
+    }
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
+
+        let mut socket = self.connect().await?;
+
+        let mut subscribe_message = serde_json::json!({
             "action": "subscribe",
-            "orderbooks" : [symbol_str],
+            "orderbooks" : [pair],
         });
+        // Trades ride on the book connection, so a new one picks them up again.
+        if self.trade_sender.lock().expect("trade sender poisoned").is_some() {
+            subscribe_message["trades"] = serde_json::json!([pair]);
+        }
 
         let json_subscribe = serde_json::to_string(&subscribe_message)
             .map_err(|e| ExchangeError::SubscriptionFailed(format!("Failed to serialize subscribe message: {}", e)))?;
 
         socket.send(Message::Text(json_subscribe.into())).await?;
 
-
-        self.active.store(true, Ordering::SeqCst);
-
-        let active = Arc::clone(&self.active);
-        let symbol_owned = symbol.to_string();
+        let symbol_owned = instrument.to_string();
         let exchange_name = self.name.clone();
         let sender = self.sender.clone();
+        let trade_sender = Arc::clone(&self.trade_sender);
+        let (outgoing, mut outgoing_frames) = mpsc::unbounded_channel::<String>();
+        *self.outgoing.lock().expect("outgoing sender poisoned") = Some(outgoing);
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
 
         tokio::spawn(async move {
-            while let Some(result) = socket.next().await {
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    Some(frame) = outgoing_frames.recv() => {
+                        if let Err(e) = socket.send(Message::Text(frame.into())).await {
+                            error!(error = %e, error_kind = "websocket", "Failed to send frame");
+                        }
+                        continue;
+                    }
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
                 match result {
                     Ok(Message::Text(text)) => {
-                        if let Ok(book_updates) = serde_json::from_str::<Vec<AlpacaBookMessage>>(&text) {
-                            for update in book_updates {
-                                for bid in update.b {
-                                    if bid.size > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Buy,
-                                            price: (bid.price * 100.0) as u64,
-                                            volume: (bid.size * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
-
-                                for ask in update.a {
-                                    if ask.size > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Sell,
-                                            price: (ask.price * 100.0) as u64,
-                                            volume: (ask.size * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        publish(&sender, epoch, parse_book_frame(&text)).await;
+                        let trades = trade_sender.lock().expect("trade sender poisoned").clone();
+                        if let Some(trades) = trades {
+                            for trade in parse_trade_frame(&text, &symbol_owned) {
+                                let _ = trades.send(trade);
                             }
                         }
                     }
                     Ok(Message::Close(_)) => {
-                        println!("Connection closed");
+                        info!("Connection closed");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
                         break;
                     }
                     Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
+                        error!(error = %e, error_kind = "websocket", "WebSocket error");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
                         break;
                     }
                     _ => {}
                 }
-
-                if !active.load(Ordering::SeqCst) {
-                    eprintln!("Unsubscribing from order book for {}", symbol_owned);
-                    socket.close(None).await.ok();
-                    active.store(false, Ordering::SeqCst);
-                    break;
-                }
             }
-        });
+        }.instrument(span));
 
         Ok(())
 
     }
-    async fn unsubscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError> {
-        match self.active.load(Ordering::SeqCst) {
-            true => {
-                self.active.store(false, Ordering::SeqCst);
-                Ok(())
-            }
+    async fn subscribe_trades(&self, instrument: &Instrument, sender: UnboundedSender<Trade>) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+
+        *self.trade_sender.lock().expect("trade sender poisoned") = Some(sender);
+        self.send_on_book_connection(serde_json::json!({
+            "action": "subscribe",
+            "trades": [pair],
+        }));
+        Ok(())
+    }
+
+    async fn unsubscribe_trades(&self, instrument: &Instrument) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+
+        if self.trade_sender.lock().expect("trade sender poisoned").take().is_none() {
+            return Err(ExchangeError::ConnectionClosed);
+        }
+        self.send_on_book_connection(serde_json::json!({
+            "action": "unsubscribe",
+            "trades": [pair],
+        }));
+        Ok(())
+    }
+
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
             false => Err(ExchangeError::ConnectionClosed),
         }
     }
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        let info = fetch_json(
+            self.client
+                .get(format!("{}/{}%2F{}", self.instruments_url, instrument.base, instrument.quote))
+                .header("Apca-Api-Key-Id", &self.api_key)
+                .header("Apca-Api-Secret-Key", &self.api_secret),
+        ).await?;
+
+        // Alpaca publishes no minimum notional for crypto assets.
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: parse_decimal(&info["price_increment"]).unwrap_or(0.0),
+            lot_size: parse_decimal(&info["min_trade_increment"]).unwrap_or(0.0),
+            min_qty: parse_decimal(&info["min_order_size"]).unwrap_or(0.0),
+            min_notional: 0.0,
+        })
+    }
+
     async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError> {
-        let pair = format!("{}/USD", order.symbol);
+        let pair = self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("Alpaca does not list {}", order.instrument)))?;
         let url = &self.order_url;
 
+        let (_, qty) = self.instruments.order_qty(&self.name, order.volume)?;
         let oq = OrderRequest {
             symbol: pair,
-            qty: order.volume.to_string(),
+            qty,
             side: match order.side {
                 OrderSide::Buy => "buy".to_string(),
                 OrderSide::Sell => "sell".to_string(),
@@ -254,4 +432,40 @@ impl Exchange for AlpacaExchange {
             ))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
+
+    const BOOK_FRAME: &str = r#"[{"T":"o","S":"SOL/USD","t":"2024-05-01T12:00:00.123Z","b":[{"p":142.51,"s":12.5}],"a":[{"p":142.55,"s":0}],"r":false}]"#;
+    const TRADE_FRAME: &str = r#"[{"T":"o","S":"SOL/USD","t":"2024-05-01T12:00:00.123Z","b":[],"a":[{"p":142.55,"s":3}],"r":false},{"T":"t","S":"SOL/USD","p":142.5,"s":1.25,"t":"2024-05-01T12:00:00.123Z","i":1,"tks":"B"}]"#;
+
+    #[test]
+    fn splits_book_and_trade_entries_of_one_frame() {
+        let orders = parse_book_frame(TRADE_FRAME);
+        assert_eq!(orders.len(), 1);
+        assert_eq!((orders[0].price, orders[0].volume), (14_255, 3_000_000));
+
+        let trades = parse_trade_frame(TRADE_FRAME, "SOL/USD");
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].price, trades[0].size), (142.5, 1.25));
+        assert!(matches!(trades[0].side, OrderSide::Buy));
+    }
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        #[test]
+        fn book_frames_never_panic(text in frames_like(BOOK_FRAME)) {
+            parse_book_frame(&text);
+        }
+
+        #[test]
+        fn trade_frames_never_panic(text in frames_like(TRADE_FRAME)) {
+            parse_trade_frame(&text, "SOL/USD");
+        }
+    }
+}
@@ -0,0 +1,560 @@
+use crate::exchanges::exchange::{Environment, Exchange};
+use crate::errors::{ExchangeError, OrderPlaceError};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::{publish, LevelTracker};
+use tracing::{error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, OBOrder, Order, OrderSide};
+use reqwest::Client;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::connect_async;
+use futures_util::stream::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use hex::encode;
+use serde::Deserialize;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SNAPSHOT_LIMIT: u32 = 1000;
+const SNAPSHOT_RETRY: Duration = Duration::from_secs(1);
+
+const VENUE: &str = "Binance";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USDT", "USDC"];
+
+#[derive(Debug, Deserialize)]
+struct DepthUpdate {
+    #[serde(rename = "E")]
+    event_time: i64,
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<[String; 2]>,
+    #[serde(rename = "a")]
+    asks: Vec<[String; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+/// A diff event that does not follow on from the local book.
+#[derive(Debug, PartialEq)]
+struct SequenceGap {
+    expected: u64,
+    received: u64,
+}
+
+/// Keeps the local book in step with Binance's diff stream. Events are
+/// buffered until a REST snapshot is applied; after that each event must
+/// start at or before the update id following the last one applied.
+#[derive(Default)]
+struct DepthSync {
+    /// Update id the local book reflects, `None` while waiting for a snapshot.
+    last_update_id: Option<u64>,
+    buffered: Vec<DepthUpdate>,
+}
+
+impl DepthSync {
+    /// Takes a diff event and returns the ones that can be applied now.
+    fn push(&mut self, update: DepthUpdate) -> Result<Vec<DepthUpdate>, SequenceGap> {
+        match self.last_update_id {
+            None => {
+                self.buffered.push(update);
+                Ok(Vec::new())
+            }
+            Some(_) => Ok(self.accept(update)?.into_iter().collect()),
+        }
+    }
+
+    /// Marks the book as synced at `last_update_id` and returns the buffered
+    /// events that come after it.
+    fn apply_snapshot(&mut self, last_update_id: u64) -> Result<Vec<DepthUpdate>, SequenceGap> {
+        self.last_update_id = Some(last_update_id);
+
+        let mut ready = Vec::new();
+        for update in std::mem::take(&mut self.buffered) {
+            ready.extend(self.accept(update)?);
+        }
+        Ok(ready)
+    }
+
+    /// Forgets the synced position so later events are buffered for a new snapshot.
+    fn reset(&mut self) {
+        self.last_update_id = None;
+        self.buffered.clear();
+    }
+
+    fn accept(&mut self, update: DepthUpdate) -> Result<Option<DepthUpdate>, SequenceGap> {
+        let last = self.last_update_id.expect("accept is only called once synced");
+
+        // Already covered by the snapshot or an earlier event.
+        if update.final_update_id <= last {
+            return Ok(None);
+        }
+        if update.first_update_id > last + 1 {
+            return Err(SequenceGap {
+                expected: last + 1,
+                received: update.first_update_id,
+            });
+        }
+
+        self.last_update_id = Some(update.final_update_id);
+        Ok(Some(update))
+    }
+}
+
+fn parse_levels(side: OrderSide, levels: Vec<[String; 2]>) -> impl Iterator<Item = (OrderSide, f64, f64)> {
+    levels.into_iter().filter_map(move |level| {
+        let price = level[0].parse::<f64>().ok()?;
+        let qty = level[1].parse::<f64>().ok()?;
+        Some((side.clone(), price, qty))
+    })
+}
+
+/// One connection's view of the depth stream: the sequencer plus the levels
+/// it has published. Asks for a new REST snapshot when the stream skips updates.
+struct BinanceFeed {
+    sync: DepthSync,
+    levels: LevelTracker,
+    needs_snapshot: bool,
+}
+
+impl BinanceFeed {
+    fn new() -> Self {
+        Self {
+            sync: DepthSync::default(),
+            levels: LevelTracker::new(VENUE),
+            needs_snapshot: false,
+        }
+    }
+
+    /// Reads a `depthUpdate` frame into the orders it releases. Control
+    /// frames, and events buffered until a snapshot, yield none.
+    fn parse_book_frame(&mut self, text: &str) -> Vec<OBOrder> {
+        let applied = match serde_json::from_str::<DepthUpdate>(text) {
+            Ok(update) => {
+                metrics().observe_feed_latency(VENUE, update.event_time);
+                self.sync.push(update)
+            }
+            Err(e) if text.contains("\"e\":\"depthUpdate\"") => {
+                metrics().feed_parse_failures.with_label_values(&[VENUE]).inc();
+                warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+                return Vec::new();
+            }
+            Err(_) => return Vec::new(),
+        };
+        let mut orders = Vec::new();
+        self.release(applied, &mut orders);
+        orders
+    }
+
+    /// Replaces the published levels with a REST snapshot and releases the
+    /// buffered events that follow it.
+    fn apply_snapshot(&mut self, snapshot: DepthSnapshot) -> Vec<OBOrder> {
+        info!(last_update_id = snapshot.last_update_id, "Applying depth snapshot");
+        let mut orders = Vec::new();
+        self.levels.snapshot(
+            parse_levels(OrderSide::Buy, snapshot.bids).chain(parse_levels(OrderSide::Sell, snapshot.asks)),
+            &mut orders,
+        );
+        let applied = self.sync.apply_snapshot(snapshot.last_update_id);
+        self.release(applied, &mut orders);
+        orders
+    }
+
+    /// True once after the stream has skipped updates and a snapshot is due.
+    fn take_snapshot_request(&mut self) -> bool {
+        std::mem::take(&mut self.needs_snapshot)
+    }
+
+    fn release(&mut self, applied: Result<Vec<DepthUpdate>, SequenceGap>, orders: &mut Vec<OBOrder>) {
+        match applied {
+            Ok(updates) => {
+                for update in updates {
+                    for (side, price, qty) in parse_levels(OrderSide::Buy, update.bids)
+                        .chain(parse_levels(OrderSide::Sell, update.asks))
+                    {
+                        self.levels.update(side, price, qty, orders);
+                    }
+                }
+            }
+            Err(gap) => {
+                warn!(
+                    expected = gap.expected,
+                    received = gap.received,
+                    error_kind = "sequence_gap",
+                    "Binance depth stream skipped updates, resyncing"
+                );
+                // What was published no longer matches the venue's book.
+                self.levels.clear(orders);
+                self.sync.reset();
+                self.needs_snapshot = true;
+            }
+        }
+    }
+}
+
+/// Fetches the REST depth snapshot, retrying until it succeeds or the feed
+/// has gone away.
+async fn fetch_snapshot(client: Client, url: String, snapshots: mpsc::Sender<DepthSnapshot>) {
+    loop {
+        let result = match client.get(&url).send().await {
+            Ok(res) => res.error_for_status().map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let snapshot = match result {
+            Ok(res) => res.json::<DepthSnapshot>().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        match snapshot {
+            Ok(snapshot) => {
+                let _ = snapshots.send(snapshot).await;
+                return;
+            }
+            Err(e) => {
+                warn!(error = %e, error_kind = "snapshot", "Failed to fetch depth snapshot");
+                if snapshots.is_closed() {
+                    return;
+                }
+                tokio::time::sleep(SNAPSHOT_RETRY).await;
+            }
+        }
+    }
+}
+
+pub struct BinanceExchange {
+    name: String,
+    api_key: String,
+    api_secret: String,
+    order_url: String,
+    depth_url: String,
+    websocket_url: String,
+    client: Client,
+    feed: FeedLifecycle,
+    sender: BookSender,
+    fees: f64,
+    instruments_url: String,
+    instruments: Arc<InstrumentRegistry>,
+}
+
+impl BinanceExchange {
+    pub fn new(api_key: String, api_secret: String, environment: Environment, instruments: Arc<InstrumentRegistry>, sender: BookSender) -> Self {
+        let (rest_url, websocket_url) = match environment {
+            Environment::Production => ("https://api.binance.com", "wss://stream.binance.com:9443/ws"),
+            Environment::Sandbox => ("https://testnet.binance.vision", "wss://stream.testnet.binance.vision/ws"),
+        };
+        BinanceExchange {
+            name: VENUE.to_string(),
+            api_key,
+            api_secret,
+            order_url: format!("{}/api/v3/order", rest_url),
+            depth_url: format!("{}/api/v3/depth", rest_url),
+            websocket_url: websocket_url.to_string(),
+            client: Client::new(),
+            feed: FeedLifecycle::default(),
+            sender,
+            fees: 0.001,
+            instruments_url: format!("{}/api/v3/exchangeInfo", rest_url),
+            instruments,
+        }
+    }
+
+    fn has_credentials(&self) -> bool {
+        !self.api_key.is_empty() && !self.api_secret.is_empty()
+    }
+
+    fn sign(&self, query: &str) -> Result<String, OrderPlaceError> {
+        let mut mac = HmacSha256::new_from_slice(self.api_secret.as_bytes())
+            .map_err(|e| OrderPlaceError::HmacError(format!("Failed to create HMAC SHA256 instance: {}", e)))?;
+        mac.update(query.as_bytes());
+        Ok(encode(mac.finalize().into_bytes()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Exchange for BinanceExchange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}{}", instrument.base, instrument.quote))
+    }
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
+
+        let stream_url = format!("{}/{}@depth@100ms", self.websocket_url, pair.to_lowercase());
+        let snapshot_url = format!("{}?symbol={}&limit={}", self.depth_url, pair, SNAPSHOT_LIMIT);
+
+        let (mut socket, _) = connect_async(&stream_url).await
+            .map_err(ExchangeError::WebSocketError)?;
+
+        let symbol_owned = instrument.to_string();
+        let exchange_name = self.name.clone();
+        let sender = self.sender.clone();
+        let client = self.client.clone();
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
+
+        tokio::spawn(async move {
+            let mut feed = BinanceFeed::new();
+            let (snapshot_sender, mut snapshots) = mpsc::channel::<DepthSnapshot>(1);
+
+            // The stream is already buffering, so the snapshot can be requested now.
+            tokio::spawn(fetch_snapshot(client.clone(), snapshot_url.clone(), snapshot_sender.clone()).in_current_span());
+
+            loop {
+                let orders = tokio::select! {
+                    result = socket.next() => {
+                        let Some(result) = result else {
+                            break;
+                        };
+                        match result {
+                            Ok(Message::Text(text)) => {
+                                metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                                feed.parse_book_frame(&text)
+                            }
+                            Ok(Message::Close(_)) => {
+                                info!("Connection closed");
+                                metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
+                                break;
+                            }
+                            Err(e) => {
+                                error!(error = %e, error_kind = "websocket", "WebSocket error");
+                                metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
+                                break;
+                            }
+                            _ => Vec::new(),
+                        }
+                    }
+                    Some(snapshot) = snapshots.recv() => feed.apply_snapshot(snapshot),
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+
+                publish(&sender, epoch, orders).await;
+                if feed.take_snapshot_request() {
+                    tokio::spawn(fetch_snapshot(client.clone(), snapshot_url.clone(), snapshot_sender.clone()).in_current_span());
+                }
+            }
+        }.instrument(span));
+
+        Ok(())
+    }
+
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
+            false => Err(ExchangeError::ConnectionClosed),
+        }
+    }
+
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        let body = fetch_json(self.client.get(&self.instruments_url).query(&[("symbol", pair.as_str())])).await?;
+
+        let filters = body["symbols"][0]["filters"]
+            .as_array()
+            .ok_or_else(|| ExchangeError::InstrumentInfo(format!("{} missing from exchangeInfo", pair)))?;
+        let filter = |filter_type: &str, field: &str| {
+            filters
+                .iter()
+                .find(|filter| filter["filterType"] == filter_type)
+                .and_then(|filter| parse_decimal(&filter[field]))
+                .unwrap_or(0.0)
+        };
+
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: filter("PRICE_FILTER", "tickSize"),
+            lot_size: filter("LOT_SIZE", "stepSize"),
+            min_qty: filter("LOT_SIZE", "minQty"),
+            min_notional: filter("NOTIONAL", "minNotional"),
+        })
+    }
+
+    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError> {
+        let pair = self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("Binance does not list {}", order.instrument)))?;
+        if !self.has_credentials() {
+            return Err(OrderPlaceError::Other("Binance API credentials are not configured".to_string()));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as u64;
+
+        let (_, quantity) = self.instruments.order_qty(&self.name, order.volume)?;
+        let params = [
+            ("symbol", pair),
+            ("side", match order.side {
+                OrderSide::Buy => "BUY",
+                OrderSide::Sell => "SELL",
+            }.to_string()),
+            ("type", "MARKET".to_string()),
+            ("quantity", quantity),
+            ("recvWindow", "5000".to_string()),
+            ("timestamp", timestamp.to_string()),
+        ];
+        let query = serde_urlencoded::to_string(params)?;
+        let signature = self.sign(&query)?;
+        let body = format!("{}&signature={}", query, signature);
+
+        let res = self.client
+            .post(&self.order_url)
+            .header("X-MBX-APIKEY", &self.api_key)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .map_err(OrderPlaceError::NetworkError)?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            let status = res.status();
+            let detail = res.text().await.unwrap_or_default();
+            Err(OrderPlaceError::Other(
+                format!("Failed to place order, response code: {} {}", status, detail)
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
+
+    fn update(first: u64, last: u64) -> DepthUpdate {
+        DepthUpdate {
+            event_time: 0,
+            first_update_id: first,
+            final_update_id: last,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    fn ids(updates: Vec<DepthUpdate>) -> Vec<(u64, u64)> {
+        updates.into_iter().map(|u| (u.first_update_id, u.final_update_id)).collect()
+    }
+
+    #[test]
+    fn buffers_until_snapshot_and_drops_stale_events() {
+        let mut sync = DepthSync::default();
+        assert!(sync.push(update(90, 95)).unwrap().is_empty());
+        assert!(sync.push(update(96, 102)).unwrap().is_empty());
+        assert!(sync.push(update(103, 110)).unwrap().is_empty());
+
+        assert_eq!(ids(sync.apply_snapshot(100).unwrap()), vec![(96, 102), (103, 110)]);
+        assert_eq!(ids(sync.push(update(111, 115)).unwrap()), vec![(111, 115)]);
+    }
+
+    #[test]
+    fn reports_gaps_after_sync() {
+        let mut sync = DepthSync::default();
+        sync.apply_snapshot(100).unwrap();
+        sync.push(update(101, 105)).unwrap();
+
+        assert_eq!(
+            sync.push(update(107, 110)).unwrap_err(),
+            SequenceGap { expected: 106, received: 107 },
+        );
+
+        sync.reset();
+        assert!(sync.push(update(111, 112)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_snapshot_older_than_buffer() {
+        let mut sync = DepthSync::default();
+        sync.push(update(120, 125)).unwrap();
+
+        assert_eq!(
+            sync.apply_snapshot(100).unwrap_err(),
+            SequenceGap { expected: 101, received: 120 },
+        );
+    }
+
+    #[test]
+    fn gap_removes_published_levels() {
+        let mut feed = BinanceFeed::new();
+        let snapshot = DepthSnapshot {
+            last_update_id: 100,
+            bids: vec![["142.51".to_string(), "1".to_string()]],
+            asks: vec![["142.55".to_string(), "2".to_string()]],
+        };
+        assert_eq!(feed.apply_snapshot(snapshot).len(), 2);
+
+        let skipped = r#"{"e":"depthUpdate","E":0,"U":105,"u":110,"b":[["142.50","1"]],"a":[]}"#;
+        let orders = feed.parse_book_frame(skipped);
+        assert_eq!(orders.len(), 2);
+        assert!(orders.iter().all(|order| order.volume == 0));
+        assert!(feed.take_snapshot_request());
+    }
+
+    const BOOK_FRAME: &str = r#"{"e":"depthUpdate","E":1714564800123,"s":"SOLUSDT","U":157,"u":160,"b":[["142.51","12.5"]],"a":[["142.55","0.00"]]}"#;
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        /// Runs fuzzed frames through the feed's parser, with a snapshot
+        /// arriving partway through.
+        #[test]
+        fn book_frames_never_panic(
+            frames in prop::collection::vec(frames_like(BOOK_FRAME), 1..8),
+            snapshot_at in any::<prop::sample::Index>(),
+            snapshot_id in any::<u64>(),
+        ) {
+            let mut feed = BinanceFeed::new();
+            let snapshot_at = snapshot_at.index(frames.len());
+            for (position, text) in frames.into_iter().enumerate() {
+                feed.parse_book_frame(&text);
+                if position == snapshot_at {
+                    feed.apply_snapshot(DepthSnapshot { last_update_id: snapshot_id, bids: Vec::new(), asks: Vec::new() });
+                }
+                feed.take_snapshot_request();
+            }
+        }
+    }
+}
+
@@ -1,9 +1,12 @@
-use crate::exchanges::exchange::Exchange;
+use crate::exchanges::exchange::{Environment, Exchange};
 use crate::errors::{ExchangeError, OrderPlaceError};
-use crate::types::{OBOrder, Order, OrderSide};
-use crate::config::{
-    TICKER,
-};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::publish;
+use tracing::{error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, OBOrder, Order, OrderSide, Trade};
 use reqwest::Client;
 use serde_json::json;
 use tokio::sync::mpsc::UnboundedSender;
@@ -11,10 +14,7 @@ use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::connect_async;
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering}
-};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
@@ -24,21 +24,102 @@ use serde::Deserialize;
 type HmacSha256 = Hmac<Sha256>;
 
 
+const VENUE: &str = "Bybit";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USDT", "USDC"];
+
 #[derive(Debug, Deserialize)]
 struct BybitDeltaMessage {
-    topic: String,
-    #[serde(rename = "type")]
-    msg_type: String,
+    #[serde(default)]
+    ts: Option<i64>,
     data: BybitDeltaData,
 }
 
 #[derive(Debug, Deserialize)]
 struct BybitDeltaData {
-    s: String,
     b: Vec<[String; 2]>,
     a: Vec<[String; 2]>,
 }
 
+#[derive(Debug, Deserialize)]
+struct BybitTradeMessage {
+    data: Vec<BybitTrade>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BybitTrade {
+    #[serde(rename = "T")]
+    timestamp: u64,
+    #[serde(rename = "S")]
+    side: String,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "v")]
+    size: String,
+}
+
+/// Reads an `orderbook` frame into the orders it carries. Control frames yield none.
+fn parse_book_frame(text: &str) -> Vec<OBOrder> {
+    let metrics = metrics();
+    let delta_msg = match serde_json::from_str::<BybitDeltaMessage>(text) {
+        Ok(delta_msg) => delta_msg,
+        // Control frames (acks, heartbeats, status) are expected not to parse.
+        Err(e) if text.contains("\"topic\":\"orderbook.") => {
+            metrics.feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    if let Some(ts) = delta_msg.ts {
+        metrics.observe_feed_latency(VENUE, ts);
+    }
+    let mut orders = Vec::new();
+    for (side, levels) in [(OrderSide::Buy, delta_msg.data.b), (OrderSide::Sell, delta_msg.data.a)] {
+        for [price, qty] in levels {
+            if let (Ok(price), Ok(qty)) = (price.parse::<f64>(), qty.parse::<f64>()) {
+                orders.push(OBOrder::new(
+                    VENUE.to_string(),
+                    side.clone(),
+                    (qty * 1_000_000.0) as u64,
+                    (price * 100.0) as u64,
+                ));
+            }
+        }
+    }
+    orders
+}
+
+/// Reads a `publicTrade` frame into the trades it carries, labelled with `symbol`.
+fn parse_trade_frame(text: &str, symbol: &str) -> Vec<Trade> {
+    let trade_msg = match serde_json::from_str::<BybitTradeMessage>(text) {
+        Ok(trade_msg) => trade_msg,
+        Err(e) if text.contains("\"topic\":\"publicTrade.") => {
+            metrics().feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse trade frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    trade_msg
+        .data
+        .into_iter()
+        .filter_map(|trade| {
+            Some(Trade {
+                exchange: VENUE.to_string(),
+                symbol: symbol.to_string(),
+                side: if trade.side == "Buy" { OrderSide::Buy } else { OrderSide::Sell },
+                price: trade.price.parse().ok()?,
+                size: trade.size.parse().ok()?,
+                timestamp_ms: trade.timestamp,
+            })
+        })
+        .collect()
+}
+
 pub struct BybitExchange {
     name: String,
     api_key: String,
@@ -46,35 +127,72 @@ pub struct BybitExchange {
     order_url: String,
     websocket_url: String,
     client: Client,
-    active: Arc<AtomicBool>,
-    sender: UnboundedSender<OBOrder>,
-    fees: f64, 
+    feed: FeedLifecycle,
+    trade_feed: FeedLifecycle,
+    sender: BookSender,
+    fees: f64,
+    instruments_url: String,
+    instruments: Arc<InstrumentRegistry>,
 }
 
 impl BybitExchange {
-    pub fn new(api_key: String, api_secret: String, sender: UnboundedSender<OBOrder>) -> Self {
+    pub fn new(api_key: String, api_secret: String, environment: Environment, instruments: Arc<InstrumentRegistry>, sender: BookSender) -> Self {
+        let (rest_url, websocket_url) = match environment {
+            Environment::Production => ("https://api.bybit.com", "wss://stream.bybit.com/v5/public/spot"),
+            Environment::Sandbox => ("https://api-testnet.bybit.com", "wss://stream-testnet.bybit.com/v5/public/spot"),
+        };
         BybitExchange {
-            name: "Bybit".to_string(),
+            name: VENUE.to_string(),
             api_key,
             api_secret,
-            order_url: "https://api.bybit.com/v5/order/create".to_string(),
-            websocket_url: "wss://stream.bybit.com/v5/public/spot".to_string(),
+            order_url: format!("{}/v5/order/create", rest_url),
+            websocket_url: websocket_url.to_string(),
             client: Client::new(),
-            active: Arc::new(AtomicBool::new(false)),
-            sender: sender,
+            feed: FeedLifecycle::default(),
+            trade_feed: FeedLifecycle::default(),
+            sender,
             fees: 0.0,
+            instruments_url: format!("{}/v5/market/instruments-info", rest_url),
+            instruments,
         }
     }
+
+    /// Signs a V5 request: hex HMAC-SHA256 of timestamp, api key, receive
+    /// window and body, sent as `X-BAPI-SIGN`.
+    pub fn sign_request(timestamp: u64, api_key: &str, recv_window: u64, body: &str, api_secret: &str) -> Result<String, OrderPlaceError> {
+        let mut mac = HmacSha256::new_from_slice(api_secret.as_bytes())
+            .map_err(|e| OrderPlaceError::HmacError(format!("Failed to create HMAC SHA256 instance: {}", e)))?;
+        mac.update(format!("{}{}{}{}", timestamp, api_key, recv_window, body).as_bytes());
+        Ok(encode(mac.finalize().into_bytes()))
+    }
 }
 
 #[async_trait::async_trait]
 impl Exchange for BybitExchange {
-    async fn subscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError> {
-        if symbol != TICKER {
-            return Err(ExchangeError::InvalidSymbol(symbol.to_string()));
-        }
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}{}", instrument.base, instrument.quote))
+    }
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
 
-        let pair = format!("{}USDT", symbol);
         let order_book_arg = format!("orderbook.50.{}", pair);
 
         let subscribe_message = json!({
@@ -88,99 +206,151 @@ impl Exchange for BybitExchange {
             .map_err(|e| ExchangeError::SerializationError(e.to_string()))?;
 
         let (mut socket, _) = connect_async(&self.websocket_url).await
-            .map_err(|e| ExchangeError::WebSocketError(e))?;
+            .map_err(ExchangeError::WebSocketError)?;
 
         socket.send(Message::Text(subscribe_message_json.into())).await
-            .map_err(|e| ExchangeError::WebSocketError(e.into()))?;
-
-        self.active.store(true, Ordering::SeqCst);
+            .map_err(ExchangeError::WebSocketError)?;
 
-        let active = Arc::clone(&self.active);
-        let symbol_owned = symbol.to_string();
+        let symbol_owned = instrument.to_string();
         let exchange_name = self.name.clone();
         let sender = self.sender.clone();
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
 
         tokio::spawn(async move {
-            while let Some(result) = socket.next().await {
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
                 match result {
                     Ok(Message::Text(text)) => {
-                        if let Ok(delta_msg) = serde_json::from_str::<BybitDeltaMessage>(&text) {
-                            for bid in delta_msg.data.b {
-                                if let (Ok(price), Ok(qty)) = (
-                                    bid[0].parse::<f64>(),
-                                    bid[1].parse::<f64>(),
-                                ) {
-                                    if qty > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Buy,
-                                            price: (price * 100.0) as u64,
-                                            volume: (qty * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                            for ask in delta_msg.data.a {
-                                if let (Ok(price), Ok(qty)) = (
-                                    ask[0].parse::<f64>(),
-                                    ask[1].parse::<f64>(),
-                                ) {
-                                    if qty > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Sell,
-                                            price: (price * 100.0) as u64,
-                                            volume: (qty * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        publish(&sender, epoch, parse_book_frame(&text)).await;
                     }
                     Ok(Message::Close(_)) => {
-                        println!("Connection closed");
+                        info!("Connection closed");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
                         break;
                     }
                     Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
+                        error!(error = %e, error_kind = "websocket", "WebSocket error");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
                         break;
                     }
                     _ => {}
                 }
+            }
+        }.instrument(span));
+        Ok(())
+    }
+
+    async fn subscribe_trades(&self, instrument: &Instrument, sender: UnboundedSender<Trade>) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.trade_feed.start();
+
+        let subscribe_message = json!({
+            "op": "subscribe",
+            "args": [format!("publicTrade.{}", pair)],
+        });
 
-                if !active.load(Ordering::SeqCst) {
-                    eprintln!("Unsubscribing from order book for {}", symbol_owned);
-                    socket.close(None).await.ok();
-                    active.store(false, Ordering::SeqCst);
+        let (mut socket, _) = connect_async(&self.websocket_url).await
+            .map_err(ExchangeError::WebSocketError)?;
+
+        socket.send(Message::Text(subscribe_message.to_string().into())).await
+            .map_err(ExchangeError::WebSocketError)?;
+
+        let symbol_owned = instrument.to_string();
+        let exchange_name = self.name.clone();
+        let metrics = metrics();
+        let span = info_span!("trades", venue = %exchange_name, symbol = %symbol_owned);
+
+        tokio::spawn(async move {
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
                     break;
+                };
+                match result {
+                    Ok(Message::Text(text)) => {
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        for trade in parse_trade_frame(&text, &symbol_owned) {
+                            if sender.send(trade).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("Trade connection closed");
+                        break;
+                    }
+                    Err(e) => {
+                        error!(error = %e, error_kind = "websocket", "Trade websocket error");
+                        break;
+                    }
+                    _ => {}
                 }
             }
-        });
+        }.instrument(span));
         Ok(())
     }
 
-    async fn unsubscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError> {
-        match self.active.load(Ordering::SeqCst) {
-            true => {
-                self.active.store(false, Ordering::SeqCst);
-                Ok(())
-            }
+    async fn unsubscribe_trades(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.trade_feed.stop() {
+            true => Ok(()),
             false => Err(ExchangeError::ConnectionClosed),
         }
     }
-    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError>{
-        if order.symbol != TICKER {
-            return  Err(OrderPlaceError::Other(
-                "Invalid symbol for Bybit exchange".to_string(),
-            ));
+
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
+            false => Err(ExchangeError::ConnectionClosed),
+        }
+    }
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        let body = fetch_json(
+            self.client.get(&self.instruments_url).query(&[("category", "spot"), ("symbol", pair.as_str())]),
+        ).await?;
+
+        let info = &body["result"]["list"][0];
+        if info.is_null() {
+            return Err(ExchangeError::InstrumentInfo(format!("{} missing from instruments-info: {}", pair, body["retMsg"])));
         }
-        let pair = format!("{}USDT", order.symbol);
+
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: parse_decimal(&info["priceFilter"]["tickSize"]).unwrap_or(0.0),
+            lot_size: parse_decimal(&info["lotSizeFilter"]["basePrecision"]).unwrap_or(0.0),
+            min_qty: parse_decimal(&info["lotSizeFilter"]["minOrderQty"]).unwrap_or(0.0),
+            min_notional: parse_decimal(&info["lotSizeFilter"]["minOrderAmt"]).unwrap_or(0.0),
+        })
+    }
+
+    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError>{
+        let pair = self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("Bybit does not list {}", order.instrument)))?;
+        let (_, qty) = self.instruments.order_qty(&self.name, order.volume)?;
         let recv_window = 5000;
 
         let body = json!({
@@ -191,7 +361,7 @@ impl Exchange for BybitExchange {
                 OrderSide::Sell => "Sell",
             },
             "orderType": "Market",
-            "qty": order.volume.to_string(),
+            "qty": qty,
             "timeInForce": "GTC",
         });
 
@@ -202,23 +372,20 @@ impl Exchange for BybitExchange {
             .expect("Time went backwards")
             .as_millis() as u64;
 
-        let sign_payload = format!("{}{}{}", timestamp, recv_window, body_str);
-        let mut mac = HmacSha256::new_from_slice(self.api_secret.as_bytes())
-            .map_err(|e| OrderPlaceError::HmacError(format!("Failed to create HMAC SHA256 instance: {}", e)))?;
-        mac.update(sign_payload.as_bytes());
-
-        let signature = encode(mac.finalize().into_bytes());
+        let signature = BybitExchange::sign_request(timestamp, &self.api_key, recv_window, &body_str, &self.api_secret)?;
 
         let res = self.client
             .post(&self.order_url)
-            .header("X-BYBIT-APIKEY", &self.api_key)
-            .header("X-BYBIT-SIGNATURE", signature)
-            .header("X-BYBIT-TIMESTAMP", timestamp.to_string())
-            .header("X-BYBIT-RECV-WINDOW", recv_window.to_string())
-            .json(&body)
+            .header("X-BAPI-API-KEY", &self.api_key)
+            .header("X-BAPI-SIGN", signature)
+            .header("X-BAPI-TIMESTAMP", timestamp.to_string())
+            .header("X-BAPI-RECV-WINDOW", recv_window.to_string())
+            .header("Content-Type", "application/json")
+            // The signature covers these exact bytes.
+            .body(body_str)
             .send()
             .await
-            .map_err(|e| OrderPlaceError::NetworkError(e))?;
+            .map_err(OrderPlaceError::NetworkError)?;
 
         if res.status().is_success() {
             Ok(())
@@ -228,4 +395,35 @@ impl Exchange for BybitExchange {
             ))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
+
+    const BOOK_FRAME: &str = r#"{"topic":"orderbook.50.SOLUSDT","type":"delta","ts":1714564800123,"data":{"s":"SOLUSDT","b":[["142.51","12.5"]],"a":[["142.55","0"]],"u":177400507,"seq":66544703342},"cts":1714564800120}"#;
+    const TRADE_FRAME: &str = r#"{"topic":"publicTrade.SOLUSDT","type":"snapshot","ts":1714564800123,"data":[{"T":1714564800120,"s":"SOLUSDT","S":"Buy","v":"1.25","p":"142.5","L":"PlusTick","i":"20f43950-d8dd-5b31-9112-a178eb6023af","BT":false}]}"#;
+
+    #[test]
+    fn signs_timestamp_key_window_and_body() {
+        let body = r#"{"category":"spot","symbol":"BTCUSDT"}"#;
+        let signature = BybitExchange::sign_request(1658384314791, "XXXXXXXXXX", 5000, body, "YYYYYYYYYY").unwrap();
+        assert_eq!(signature, "b2a11a320c923d56d81b2eb2b8527963083933449d8fc22e41874ccf22a31ee2");
+    }
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        #[test]
+        fn book_frames_never_panic(text in frames_like(BOOK_FRAME)) {
+            parse_book_frame(&text);
+        }
+
+        #[test]
+        fn trade_frames_never_panic(text in frames_like(TRADE_FRAME)) {
+            parse_trade_frame(&text, "SOL/USDT");
+        }
+    }
+}
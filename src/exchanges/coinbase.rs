@@ -0,0 +1,478 @@
+use crate::exchanges::exchange::Exchange;
+use crate::errors::{ExchangeError, OrderPlaceError};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::{publish, LevelTracker};
+use tracing::{error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, OBOrder, Order, OrderSide};
+use reqwest::Client;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::connect_async;
+use futures_util::sink::SinkExt;
+use futures_util::stream::StreamExt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::{pkcs8::DecodePrivateKey, SecretKey};
+use rand::Rng;
+use serde::Deserialize;
+
+const ORDER_PATH: &str = "/api/v3/brokerage/orders";
+const JWT_LIFETIME_SECS: u64 = 120;
+
+const VENUE: &str = "Coinbase";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USD"];
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseMessage {
+    channel: String,
+    #[serde(default)]
+    timestamp: Option<String>,
+    sequence_num: u64,
+    #[serde(default)]
+    events: Vec<CoinbaseEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseEvent {
+    // Subscription acks carry events without a type, so default it.
+    #[serde(rename = "type", default)]
+    event_type: String,
+    #[serde(default)]
+    updates: Vec<CoinbaseLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseLevel {
+    side: String,
+    price_level: String,
+    new_quantity: String,
+}
+
+/// The sequence number that should have followed `last`, if `received` is not it.
+fn missed_sequence(last: Option<u64>, received: u64) -> Option<u64> {
+    let expected = last?.wrapping_add(1);
+    (received != expected).then_some(expected)
+}
+
+/// One connection's view of the level2 channel: the sequence it has reached
+/// and the levels it has published. Asks to resubscribe when messages go missing.
+struct CoinbaseFeed {
+    last_sequence: Option<u64>,
+    levels: LevelTracker,
+    /// Set while waiting for the snapshot of a fresh subscription.
+    resyncing: bool,
+    needs_resubscribe: bool,
+}
+
+impl CoinbaseFeed {
+    fn new() -> Self {
+        Self {
+            last_sequence: None,
+            levels: LevelTracker::new(VENUE),
+            resyncing: false,
+            needs_resubscribe: false,
+        }
+    }
+
+    /// True once after a sequence gap, when level2 should be resubscribed.
+    fn take_resubscribe_request(&mut self) -> bool {
+        std::mem::take(&mut self.needs_resubscribe)
+    }
+
+    /// Reads a frame into the orders it carries. Control frames, and updates
+    /// that arrive while resyncing, yield none.
+    fn parse_book_frame(&mut self, text: &str) -> Vec<OBOrder> {
+        let metrics = metrics();
+        let message = match serde_json::from_str::<CoinbaseMessage>(text) {
+            Ok(message) => message,
+            Err(e) if text.contains("\"channel\":\"l2_data\"") => {
+                metrics.feed_parse_failures.with_label_values(&[VENUE]).inc();
+                warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+                return Vec::new();
+            }
+            Err(_) => return Vec::new(),
+        };
+
+        // Sequence numbers run across every channel on the connection,
+        // heartbeats included, so a gap means level2 updates may be lost.
+        let mut orders = Vec::new();
+        if let Some(expected) = missed_sequence(self.last_sequence, message.sequence_num) {
+            warn!(
+                expected,
+                received = message.sequence_num,
+                error_kind = "sequence_gap",
+                "Coinbase feed skipped messages, resubscribing"
+            );
+            self.levels.clear(&mut orders);
+            self.resyncing = true;
+            self.needs_resubscribe = true;
+        }
+        self.last_sequence = Some(message.sequence_num);
+
+        if message.channel != "l2_data" {
+            return orders;
+        }
+        if let Some(timestamp) = &message.timestamp {
+            metrics.observe_feed_latency_rfc3339(VENUE, timestamp);
+        }
+
+        for event in message.events {
+            if event.event_type == "snapshot" {
+                self.resyncing = false;
+            } else if self.resyncing {
+                continue;
+            }
+            let levels = event.updates.into_iter().filter_map(|level| {
+                let price = level.price_level.parse::<f64>().ok()?;
+                let qty = level.new_quantity.parse::<f64>().ok()?;
+                let side = if level.side == "bid" { OrderSide::Buy } else { OrderSide::Sell };
+                Some((side, price, qty))
+            });
+
+            if event.event_type == "snapshot" {
+                self.levels.snapshot(levels, &mut orders);
+            } else {
+                for (side, price, qty) in levels {
+                    self.levels.update(side, price, qty, &mut orders);
+                }
+            }
+        }
+        orders
+    }
+}
+
+pub struct CoinbaseExchange {
+    name: String,
+    /// CDP API key name, `organizations/{org_id}/apiKeys/{key_id}`.
+    api_key: String,
+    /// EC private key in PEM form, SEC1 or PKCS#8.
+    api_secret: String,
+    rest_host: String,
+    websocket_url: String,
+    client: Client,
+    feed: FeedLifecycle,
+    sender: BookSender,
+    fees: f64,
+    instruments: Arc<InstrumentRegistry>,
+}
+
+impl CoinbaseExchange {
+    pub fn new(api_key: String, api_secret: String, instruments: Arc<InstrumentRegistry>, sender: BookSender) -> Self {
+        let rest_host = "api.coinbase.com";
+        CoinbaseExchange {
+            name: VENUE.to_string(),
+            api_key,
+            // Keys copied out of a .env file usually keep their newlines escaped.
+            api_secret: api_secret.replace("\\n", "\n"),
+            rest_host: rest_host.to_string(),
+            websocket_url: "wss://advanced-trade-ws.coinbase.com".to_string(),
+            client: Client::new(),
+            feed: FeedLifecycle::default(),
+            sender,
+            fees: 0.006,
+            instruments,
+        }
+    }
+
+    fn has_credentials(&self) -> bool {
+        !self.api_key.is_empty() && !self.api_secret.is_empty()
+    }
+
+    /// Builds an ES256 JWT for the CDP API. REST calls bind the token to a
+    /// single `uri` ("METHOD host/path"); websocket tokens leave it out.
+    pub fn build_jwt(&self, uri: Option<&str>) -> Result<String, OrderPlaceError> {
+        let secret_key = SecretKey::from_sec1_pem(&self.api_secret)
+            .or_else(|_| SecretKey::from_pkcs8_pem(&self.api_secret))
+            .map_err(|e| OrderPlaceError::Other(format!("Invalid Coinbase private key: {}", e)))?;
+        let signing_key = SigningKey::from(secret_key);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let nonce: [u8; 16] = rand::thread_rng().gen();
+
+        let header = json!({
+            "alg": "ES256",
+            "typ": "JWT",
+            "kid": self.api_key,
+            "nonce": hex::encode(nonce),
+        });
+        let mut claims = json!({
+            "sub": self.api_key,
+            "iss": "cdp",
+            "nbf": now,
+            "exp": now + JWT_LIFETIME_SECS,
+        });
+        if let Some(uri) = uri {
+            claims["uri"] = json!(uri);
+        }
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string()),
+        );
+        let signature: Signature = signing_key.sign(signing_input.as_bytes());
+
+        Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes())))
+    }
+}
+
+#[async_trait::async_trait]
+impl Exchange for CoinbaseExchange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}-{}", instrument.base, instrument.quote))
+    }
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
+
+        let mut subscribe_message = json!({
+            "type": "subscribe",
+            "product_ids": [pair],
+            "channel": "level2",
+        });
+        // level2 is public, but authenticated subscriptions get better rate limits.
+        if self.has_credentials() {
+            let jwt = self.build_jwt(None)
+                .map_err(|e| ExchangeError::SubscriptionFailed(e.to_string()))?;
+            subscribe_message["jwt"] = json!(jwt);
+        }
+
+        // Heartbeats keep the sequence moving, so a gap shows up even when the book is quiet.
+        let mut heartbeats_message = json!({
+            "type": "subscribe",
+            "product_ids": [pair],
+            "channel": "heartbeats",
+        });
+        if let Some(jwt) = subscribe_message.get("jwt") {
+            heartbeats_message["jwt"] = jwt.clone();
+        }
+        // level2 is public, and a JWT would have expired by the time a gap
+        // forces a resubscription, so those go without one.
+        let resubscribe_messages = [
+            json!({ "type": "unsubscribe", "product_ids": [pair], "channel": "level2" }).to_string(),
+            json!({ "type": "subscribe", "product_ids": [pair], "channel": "level2" }).to_string(),
+        ];
+
+        let (mut socket, _) = connect_async(&self.websocket_url).await?;
+        for message in [&subscribe_message, &heartbeats_message] {
+            let message_json = serde_json::to_string(message)
+                .map_err(|e| ExchangeError::SerializationError(e.to_string()))?;
+            socket.send(Message::Text(message_json.into())).await?;
+        }
+
+        let symbol_owned = instrument.to_string();
+        let exchange_name = self.name.clone();
+        let sender = self.sender.clone();
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
+
+        tokio::spawn(async move {
+            let mut feed = CoinbaseFeed::new();
+
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                match result {
+                    Ok(Message::Text(text)) => {
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        publish(&sender, epoch, feed.parse_book_frame(&text)).await;
+                        if feed.take_resubscribe_request() {
+                            // A new subscription starts with a fresh snapshot.
+                            for message in &resubscribe_messages {
+                                if let Err(e) = socket.send(Message::Text(message.clone().into())).await {
+                                    error!(error = %e, error_kind = "websocket", "Failed to resubscribe");
+                                }
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("Connection closed");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
+                        break;
+                    }
+                    Err(e) => {
+                        error!(error = %e, error_kind = "websocket", "WebSocket error");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }.instrument(span));
+
+        Ok(())
+    }
+
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
+            false => Err(ExchangeError::ConnectionClosed),
+        }
+    }
+
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        let info = fetch_json(
+            self.client.get(format!(
+                "https://{}/api/v3/brokerage/market/products/{}-{}",
+                self.rest_host, instrument.base, instrument.quote,
+            )),
+        ).await?;
+
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: parse_decimal(&info["quote_increment"]).unwrap_or(0.0),
+            lot_size: parse_decimal(&info["base_increment"]).unwrap_or(0.0),
+            min_qty: parse_decimal(&info["base_min_size"]).unwrap_or(0.0),
+            min_notional: parse_decimal(&info["quote_min_size"]).unwrap_or(0.0),
+        })
+    }
+
+    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError> {
+        if !self.has_credentials() {
+            return Err(OrderPlaceError::Other("Coinbase API credentials are not configured".to_string()));
+        }
+
+        let product_id = self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("Coinbase does not list {}", order.instrument)))?;
+        let (_, base_size) = self.instruments.order_qty(&self.name, order.volume)?;
+        let client_order_id: [u8; 16] = rand::thread_rng().gen();
+        let body = json!({
+            "client_order_id": hex::encode(client_order_id),
+            "product_id": product_id,
+            "side": match order.side {
+                OrderSide::Buy => "BUY",
+                OrderSide::Sell => "SELL",
+            },
+            "order_configuration": {
+                "market_market_ioc": {
+                    "base_size": base_size,
+                }
+            }
+        });
+
+        let jwt = self.build_jwt(Some(&format!("POST {}{}", self.rest_host, ORDER_PATH)))?;
+
+        let res = self.client
+            .post(format!("https://{}{}", self.rest_host, ORDER_PATH))
+            .bearer_auth(jwt)
+            .json(&body)
+            .send()
+            .await
+            .map_err(OrderPlaceError::Http)?;
+
+        if !res.status().is_success() {
+            return Err(OrderPlaceError::Other(
+                format!("Failed to place order, response code: {}", res.status())
+            ));
+        }
+
+        // Rejections come back as 200 with `success: false`.
+        let response: serde_json::Value = res.json().await.map_err(OrderPlaceError::Http)?;
+        if response["success"].as_bool() == Some(true) {
+            Ok(())
+        } else {
+            Err(OrderPlaceError::Other(format!("Failed to place order: {}", response["error_response"])))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
+
+    const BOOK_FRAME: &str = r#"{"channel":"l2_data","client_id":"","timestamp":"2024-05-01T12:00:00.123Z","sequence_num":7,"events":[{"type":"update","product_id":"SOL-USD","updates":[{"side":"bid","event_time":"2024-05-01T12:00:00.1Z","price_level":"142.51","new_quantity":"12.5"},{"side":"offer","event_time":"2024-05-01T12:00:00.1Z","price_level":"142.55","new_quantity":"0"}]}]}"#;
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        #[test]
+        fn book_frames_never_panic(frames in prop::collection::vec(frames_like(BOOK_FRAME), 1..8)) {
+            let mut feed = CoinbaseFeed::new();
+            for text in frames {
+                feed.parse_book_frame(&text);
+                feed.take_resubscribe_request();
+            }
+        }
+    }
+
+    fn frame(sequence_num: u64, event_type: &str, price: &str) -> String {
+        json!({
+            "channel": "l2_data",
+            "sequence_num": sequence_num,
+            "events": [{
+                "type": event_type,
+                "updates": [{ "side": "bid", "price_level": price, "new_quantity": "1" }],
+            }],
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn gap_clears_levels_until_the_next_snapshot() {
+        let mut feed = CoinbaseFeed::new();
+        assert_eq!(feed.parse_book_frame(&frame(1, "snapshot", "100")).len(), 1);
+        assert_eq!(feed.parse_book_frame(&frame(2, "update", "101")).len(), 1);
+        assert!(!feed.take_resubscribe_request());
+
+        // Sequence 3 went missing: both levels come out and level2 is resubscribed.
+        let cleared = feed.parse_book_frame(&frame(4, "update", "102"));
+        assert_eq!(cleared.len(), 2);
+        assert!(cleared.iter().all(|order| order.volume == 0));
+        assert!(feed.take_resubscribe_request());
+
+        assert!(feed.parse_book_frame(&frame(5, "update", "103")).is_empty());
+        let snapshot = feed.parse_book_frame(&frame(6, "snapshot", "104"));
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].price, 10_400);
+        assert_eq!(feed.parse_book_frame(&frame(7, "update", "105")).len(), 1);
+    }
+
+    #[test]
+    fn sequence_wraps_without_overflow() {
+        assert_eq!(missed_sequence(None, 5), None);
+        assert_eq!(missed_sequence(Some(4), 5), None);
+        assert_eq!(missed_sequence(Some(4), 7), Some(5));
+        assert_eq!(missed_sequence(Some(u64::MAX), 0), None);
+    }
+}
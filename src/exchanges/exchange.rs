@@ -1,11 +1,69 @@
 use async_trait::async_trait;
+use clap::ValueEnum;
+use crate::config;
 use crate::errors::{ExchangeError, OrderPlaceError};
-use crate::types::{Order};
+use tokio::sync::mpsc::UnboundedSender;
+use crate::types::{Instrument, InstrumentInfo, Order, Trade};
+
+/// Which deployment of each venue to talk to. An adapter switches its REST
+/// and websocket endpoints together, and the session picks matching credentials.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Environment {
+    /// Live venues trading real funds
+    #[default]
+    Production,
+    /// Testnet, paper or demo endpoints where orders do not move real funds
+    Sandbox,
+}
+
+impl Environment {
+    /// Sandbox orders are always allowed; production needs `config::LIVE_TRADING`.
+    pub fn allows_live_orders(self) -> bool {
+        config::LIVE_TRADING || self == Environment::Sandbox
+    }
+}
 
 #[async_trait]
 pub trait Exchange {
-    async fn subscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError>;
-    async fn unsubscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError>;
+    fn name(&self) -> &str;
+
+    /// Taker fee as a fraction of notional, e.g. 0.0026 for 26 bps.
+    fn taker_fee(&self) -> f64;
+
+    /// Whether the order book subscription is currently live.
+    fn is_connected(&self) -> bool;
+
+    /// How this venue spells `instrument`, or `None` if the adapter does not
+    /// trade that quote currency.
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String>;
+
+    /// Opens a book connection that tags its updates with `epoch`, stopping
+    /// any earlier one. `epoch` is 0 until the book has cleared the venue, and
+    /// then whatever `UnifiedOrderBook::clear_venue` last returned.
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError>;
+    async fn unsubscribe_ob(&self, instrument: &Instrument) -> Result<(), ExchangeError>;
+
+    /// Restarts the book subscription so the venue sends a fresh snapshot,
+    /// after the session dropped some of its updates and cleared the venue
+    /// into `epoch`.
+    async fn resync_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        // Subscribing stops the old connection, and anything it queued before
+        // stopping carries an older epoch, which the book drops.
+        self.subscribe_ob(instrument, epoch).await
+    }
+
+    /// Streams public trades for `instrument` into `sender` until
+    /// `unsubscribe_trades`, independently of the book subscription.
+    async fn subscribe_trades(&self, _instrument: &Instrument, _sender: UnboundedSender<Trade>) -> Result<(), ExchangeError> {
+        Err(ExchangeError::Unsupported(format!("{} trade stream", self.name())))
+    }
+
+    async fn unsubscribe_trades(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        Err(ExchangeError::Unsupported(format!("{} trade stream", self.name())))
+    }
+
+    /// Fetches the venue's tick size, lot size and order minimums for `instrument`.
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError>;
 
     async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError>;
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::watch;
+
+/// Starts and stops one of an adapter's streams. Every start begins a new
+/// generation, and a connection runs only while its generation is current,
+/// so a restart can never leave the previous connection running.
+pub struct FeedLifecycle {
+    generation: watch::Sender<u64>,
+    /// Generation of the connection that is up, or 0 when none is.
+    live: Arc<AtomicU64>,
+}
+
+impl Default for FeedLifecycle {
+    fn default() -> Self {
+        Self {
+            generation: watch::Sender::new(0),
+            live: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl FeedLifecycle {
+    /// Begins a new generation, stopping any earlier connection, and returns
+    /// the token the new connection runs under.
+    pub fn start(&self) -> FeedToken {
+        let mut generation = 0;
+        self.generation.send_modify(|current| {
+            *current += 1;
+            generation = *current;
+        });
+        self.live.store(generation, Ordering::SeqCst);
+        FeedToken {
+            generation,
+            receiver: self.generation.subscribe(),
+            live: self.live.clone(),
+        }
+    }
+
+    /// Whether the current generation's connection is up.
+    pub fn is_connected(&self) -> bool {
+        self.live.load(Ordering::SeqCst) != 0
+    }
+
+    /// Stops the current connection. Returns whether one was up.
+    pub fn stop(&self) -> bool {
+        self.generation.send_modify(|current| *current += 1);
+        self.live.swap(0, Ordering::SeqCst) != 0
+    }
+}
+
+/// Held by the task that owns one connection. Dropping it marks the
+/// connection as down, unless a newer one has already started.
+pub struct FeedToken {
+    generation: u64,
+    receiver: watch::Receiver<u64>,
+    live: Arc<AtomicU64>,
+}
+
+impl FeedToken {
+    /// Resolves once the connection should close: its stream was stopped,
+    /// restarted, or the adapter was dropped.
+    pub async fn stopped(&mut self) {
+        let generation = self.generation;
+        let _ = self.receiver.wait_for(|&current| current != generation).await;
+    }
+}
+
+impl Drop for FeedToken {
+    fn drop(&mut self) {
+        let _ = self.live.compare_exchange(self.generation, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn restart_stops_the_previous_connection() {
+        let feed = FeedLifecycle::default();
+        let mut first = feed.start();
+        let mut second = feed.start();
+
+        tokio::time::timeout(Duration::from_secs(1), first.stopped()).await.unwrap();
+        // The old connection going away does not mark the new one down.
+        drop(first);
+        assert!(feed.stop());
+        tokio::time::timeout(Duration::from_secs(1), second.stopped()).await.unwrap();
+        assert!(!feed.stop());
+    }
+}
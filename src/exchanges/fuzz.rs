@@ -0,0 +1,78 @@
+use proptest::prelude::*;
+use serde_json::{Map, Value};
+
+/// Frames per fuzz target. Far more than proptest's default, since a frame
+/// only finds anything once a mutation lands on the right field.
+pub fn config() -> ProptestConfig {
+    ProptestConfig::with_cases(1024)
+}
+
+/// Frames shaped like `sample`, a real message from the venue, with leaves
+/// replaced, fields dropped and arrays shortened or repeated at random, plus
+/// the same frames cut short and arbitrary text. Parsers fed these should
+/// reject what they cannot read rather than panic.
+pub fn frames_like(sample: &str) -> BoxedStrategy<String> {
+    let sample: Value = serde_json::from_str(sample).expect("valid sample frame");
+    let frames = mutate(&sample).prop_map(|frame| frame.to_string());
+    let truncated = (frames.clone(), any::<prop::sample::Index>()).prop_map(|(frame, cut)| {
+        let mut end = cut.index(frame.len() + 1);
+        while !frame.is_char_boundary(end) {
+            end -= 1;
+        }
+        frame[..end].to_string()
+    });
+    prop_oneof![
+        6 => frames,
+        1 => truncated,
+        1 => any::<String>(),
+    ]
+    .boxed()
+}
+
+fn leaf() -> BoxedStrategy<Value> {
+    prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<u64>().prop_map(Value::from),
+        any::<f64>().prop_map(Value::from),
+        // Uniform integers almost never hit the edges, where overflows live.
+        prop::sample::select(vec![Value::from(i64::MIN), Value::from(i64::MAX), Value::from(u64::MAX), Value::from(-1), Value::from(0)]),
+        any::<String>().prop_map(Value::from),
+        // Venues send most numbers as strings.
+        "-?[0-9]{0,20}(\\.[0-9]{0,12})?(e-?[0-9]{1,3})?".prop_map(Value::from),
+        Just(Value::Array(Vec::new())),
+    ]
+    .boxed()
+}
+
+fn mutate(value: &Value) -> BoxedStrategy<Value> {
+    let kept = match value {
+        Value::Object(fields) => {
+            let fields: Vec<BoxedStrategy<Option<(String, Value)>>> = fields
+                .iter()
+                .map(|(key, value)| {
+                    let key = key.clone();
+                    prop_oneof![
+                        24 => mutate(value).prop_map(move |value| Some((key.clone(), value))),
+                        1 => Just(None),
+                    ]
+                    .boxed()
+                })
+                .collect();
+            fields
+                .prop_map(|fields| Value::Object(fields.into_iter().flatten().collect::<Map<_, _>>()))
+                .boxed()
+        }
+        Value::Array(items) => {
+            let len = items.len() * 2;
+            let items: Vec<BoxedStrategy<Value>> = items.iter().chain(items).map(mutate).collect();
+            (items, 0..=len)
+                .prop_map(|(items, keep)| Value::Array(items.into_iter().take(keep).collect()))
+                .boxed()
+        }
+        other => Just(other.clone()).boxed(),
+    };
+    // Mutate sparingly so most frames still decode and reach the code behind the parser.
+    prop_oneof![12 => kept, 1 => leaf()].boxed()
+}
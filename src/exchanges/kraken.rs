@@ -1,14 +1,20 @@
 use crate::exchanges::exchange::Exchange;
 use crate::errors::{ExchangeError, OrderPlaceError};
-use crate::types::{Order, OrderSide, OBOrder};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::publish;
+use tracing::{error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, Order, OrderSide, OBOrder, Trade};
 use crate::config::{
     ORDER_BOOK_DEPTH,
-    TICKER,
 };
 use serde::Serialize;
 use reqwest::Client;
-use tokio::sync::mpsc::UnboundedSender;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::json;
+use tokio::sync::{mpsc::{self, UnboundedSender}, oneshot, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256, Sha512};
 use base64::{engine::general_purpose, Engine as _};
@@ -17,13 +23,15 @@ use tokio_tungstenite::connect_async;
 use std::collections::HashMap;
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering}
-};
+use std::sync::Arc;
 
 use serde::Deserialize;
 
+const VENUE: &str = "Kraken";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USD", "USDT"];
+
 #[derive(Debug, Deserialize)]
 struct WsMessage {
     data: Vec<BookData>,
@@ -33,6 +41,8 @@ struct WsMessage {
 struct BookData {
     bids: Vec<Level>,
     asks: Vec<Level>,
+    #[serde(default)]
+    timestamp: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -41,19 +51,126 @@ struct Level {
     qty: f64,
 }
 
+#[derive(Debug, Deserialize)]
+struct TradeMessage {
+    data: Vec<TradeData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeData {
+    side: String,
+    price: f64,
+    qty: f64,
+    timestamp: String,
+}
+
+/// Reads a `book` frame into the orders it carries. Control frames yield none.
+fn parse_book_frame(text: &str) -> Vec<OBOrder> {
+    let metrics = metrics();
+    let ws_msg = match serde_json::from_str::<WsMessage>(text) {
+        Ok(ws_msg) => ws_msg,
+        // Control frames (acks, heartbeats, status) are expected not to parse.
+        Err(e) if text.contains("\"channel\":\"book\"") => {
+            metrics.feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    let mut orders = Vec::new();
+    for book_data in ws_msg.data {
+        if let Some(timestamp) = &book_data.timestamp {
+            metrics.observe_feed_latency_rfc3339(VENUE, timestamp);
+        }
+        for (side, levels) in [(OrderSide::Buy, book_data.bids), (OrderSide::Sell, book_data.asks)] {
+            for level in levels {
+                orders.push(OBOrder::new(
+                    VENUE.to_string(),
+                    side.clone(),
+                    (level.qty * 1_000_000.0) as u64,
+                    (level.price * 100.0) as u64,
+                ));
+            }
+        }
+    }
+    orders
+}
+
+/// Reads a `trade` frame into the trades it carries, labelled with `symbol`.
+fn parse_trade_frame(text: &str, symbol: &str) -> Vec<Trade> {
+    let trade_msg = match serde_json::from_str::<TradeMessage>(text) {
+        Ok(trade_msg) => trade_msg,
+        Err(e) if text.contains("\"channel\":\"trade\"") => {
+            metrics().feed_parse_failures.with_label_values(&[VENUE]).inc();
+            warn!(error = %e, error_kind = "parse", "Failed to parse trade frame");
+            return Vec::new();
+        }
+        Err(_) => return Vec::new(),
+    };
+
+    trade_msg
+        .data
+        .into_iter()
+        .map(|trade| Trade {
+            exchange: VENUE.to_string(),
+            symbol: symbol.to_string(),
+            side: if trade.side == "buy" { OrderSide::Buy } else { OrderSide::Sell },
+            price: trade.price,
+            size: trade.qty,
+            timestamp_ms: chrono::DateTime::parse_from_rfc3339(&trade.timestamp)
+                .map_or(0, |time| time.timestamp_millis() as u64),
+        })
+        .collect()
+}
+
 
 type HmacSha512 = Hmac<Sha512>;
 
+const ADD_ORDER_PATH: &str = "/0/private/AddOrder";
+const WS_TOKEN_PATH: &str = "/0/private/GetWebSocketsToken";
+const WS_ORDER_TIMEOUT: Duration = Duration::from_secs(5);
+
+type WsOrderReply = oneshot::Sender<Result<serde_json::Value, String>>;
+
+/// A persistent connection to Kraken's authenticated websocket, owned by a
+/// background task that matches responses to requests by `req_id`.
+struct WsOrderSession {
+    token: String,
+    requests: mpsc::UnboundedSender<(serde_json::Value, WsOrderReply)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsResponse {
+    req_id: u64,
+    success: bool,
+    #[serde(default)]
+    result: serde_json::Value,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+enum WsOrderError {
+    /// The order never reached Kraken, so it is safe to retry over REST.
+    Unavailable(String),
+    /// Kraken rejected the order, or its outcome is unknown.
+    Failed(OrderPlaceError),
+}
+
 pub struct KrakenExchange {
     name: String,
     api_key: String,
     api_secret: String,
-    order_url: String,
+    rest_url: String,
     websocket_url: String,
+    auth_websocket_url: String,
+    order_session: Mutex<Option<WsOrderSession>>,
     client: Client,
-    active: Arc<AtomicBool>,
-    sender: UnboundedSender<OBOrder>,
-    fees: f64, 
+    feed: FeedLifecycle,
+    trade_feed: FeedLifecycle,
+    sender: BookSender,
+    fees: f64,
+    instruments: Arc<InstrumentRegistry>,
 }
 #[derive(Serialize)]
 pub struct OrderBookSubscribe {
@@ -69,17 +186,21 @@ pub struct OrderBookSubscribeParams {
 }
 
 impl KrakenExchange {
-    pub fn new(api_key: String, api_secret: String, sender: UnboundedSender<OBOrder>) -> Self {
+    pub fn new(api_key: String, api_secret: String, instruments: Arc<InstrumentRegistry>, sender: BookSender) -> Self {
         KrakenExchange {
-            name: "Kraken".to_string(),
+            name: VENUE.to_string(),
             api_key,
             api_secret,
-            order_url: "https://api.kraken.com/0/private/AddOrder".to_string(),
+            rest_url: "https://api.kraken.com".to_string(),
             websocket_url: "wss://ws.kraken.com/v2".to_string(),
+            auth_websocket_url: "wss://ws-auth.kraken.com/v2".to_string(),
+            order_session: Mutex::new(None),
             client: Client::new(),
-            active: Arc::new(AtomicBool::new(false)),
+            feed: FeedLifecycle::default(),
+            trade_feed: FeedLifecycle::default(),
             sender,
-            fees: 0.0026, 
+            fees: 0.0026,
+            instruments,
         }
     }
     pub fn get_nonce() -> String {
@@ -109,142 +230,392 @@ impl KrakenExchange {
         let signature = mac.finalize().into_bytes();
         Ok(general_purpose::STANDARD.encode(signature))
     }
-}
 
-    #[async_trait::async_trait]
-    impl Exchange for KrakenExchange {
+    /// Sends a signed request to a private REST endpoint and returns its
+    /// `result`. Kraken reports failures in the `error` array, often with a 200.
+    async fn private_post(&self, path: &str, mut params: Vec<(&str, String)>) -> Result<serde_json::Value, OrderPlaceError> {
+        let nonce = KrakenExchange::get_nonce();
+        params.insert(0, ("nonce", nonce.clone()));
+
+        let post_data = serde_urlencoded::to_string(&params)
+            .map_err(OrderPlaceError::Serialization)?;
 
-    async fn subscribe_ob(&self, symbol: &str) -> Result<(), ExchangeError> {
+        // The signature covers the URI path, not the full URL.
+        let signature = KrakenExchange::sign_request(path, &nonce, &post_data, &self.api_secret)?;
 
-        if symbol != TICKER {
-            return Err(ExchangeError::InvalidSymbol(symbol.to_string()));
+        let res = self.client.post(format!("{}{}", self.rest_url, path))
+            .header("API-Key", &self.api_key)
+            .header("API-Sign", signature)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(post_data)
+            .send()
+            .await
+            .map_err(OrderPlaceError::Http)?;
+
+        if !res.status().is_success() {
+            let error_message = res.text().await.map_err(OrderPlaceError::Http)?;
+            return Err(OrderPlaceError::Other(format!("Failed to place order: {}", error_message)));
         }
 
-        let symbol_str = format!("{}/USD", symbol);
+        let body: serde_json::Value = res.json().await.map_err(OrderPlaceError::Http)?;
+        match body["error"].as_array() {
+            Some(errors) if !errors.is_empty() => {
+                Err(OrderPlaceError::Other(format!("Kraken error: {}", body["error"])))
+            }
+            _ => Ok(body["result"].clone()),
+        }
+    }
 
-        let subscribe_message = serde_json::json!({
-            "method": "subscribe",
-            "params": {
-                "channel": "book",
-                "symbol": [symbol_str],
-                "depth": ORDER_BOOK_DEPTH,
-                "snapshot": false,
+    fn order_symbol(&self, order: &Order) -> Result<String, OrderPlaceError> {
+        self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("Kraken does not list {}", order.instrument)))
+    }
+
+    async fn place_order_rest(&self, order: &Order) -> Result<(), OrderPlaceError> {
+        let (_, volume) = self.instruments.order_qty(&self.name, order.volume)?;
+        let params = vec![
+            ("ordertype", "market".to_string()),
+            ("type", match order.side {
+                OrderSide::Buy => "buy",
+                OrderSide::Sell => "sell",
+            }.to_string()),
+            ("volume", volume),
+            ("pair", self.order_symbol(order)?),
+        ];
+
+        self.private_post(ADD_ORDER_PATH, params).await.map(|_| ())
+    }
+
+    /// Opens the authenticated order connection. Kraken's token only has to
+    /// be valid when the connection is made; it then lasts as long as the socket.
+    async fn connect_order_session(&self) -> Result<WsOrderSession, String> {
+        let result = self.private_post(WS_TOKEN_PATH, Vec::new()).await
+            .map_err(|e| format!("Failed to get websocket token: {}", e))?;
+        let token = result["token"].as_str()
+            .ok_or_else(|| "Websocket token missing from response".to_string())?
+            .to_string();
+
+        let (mut socket, _) = connect_async(&self.auth_websocket_url).await
+            .map_err(|e| format!("Failed to connect order websocket: {}", e))?;
+
+        let (requests, mut incoming) = mpsc::unbounded_channel::<(serde_json::Value, WsOrderReply)>();
+        let span = info_span!("order_session", venue = %self.name);
+
+        tokio::spawn(async move {
+            let mut next_req_id = 1u64;
+            let mut pending: HashMap<u64, WsOrderReply> = HashMap::new();
+
+            loop {
+                tokio::select! {
+                    request = incoming.recv() => {
+                        let Some((mut message, reply)) = request else {
+                            break;
+                        };
+                        message["req_id"] = json!(next_req_id);
+                        if let Err(e) = socket.send(Message::Text(message.to_string().into())).await {
+                            let _ = reply.send(Err(e.to_string()));
+                            break;
+                        }
+                        pending.insert(next_req_id, reply);
+                        next_req_id += 1;
+                    }
+                    result = socket.next() => {
+                        match result {
+                            Some(Ok(Message::Text(text))) => {
+                                // Heartbeats and status frames carry no req_id.
+                                if let Ok(response) = serde_json::from_str::<WsResponse>(&text) {
+                                    if let Some(reply) = pending.remove(&response.req_id) {
+                                        let outcome = if response.success {
+                                            Ok(response.result)
+                                        } else {
+                                            Err(response.error.unwrap_or_else(|| "unknown error".to_string()))
+                                        };
+                                        let _ = reply.send(outcome);
+                                    }
+                                }
+                            }
+                            Some(Ok(Message::Close(_))) | None => {
+                                info!("Order connection closed");
+                                break;
+                            }
+                            Some(Err(e)) => {
+                                error!(error = %e, error_kind = "websocket", "Order connection error");
+                                break;
+                            }
+                            Some(Ok(_)) => {}
+                        }
+                    }
+                }
             }
+
+            for (_, reply) in pending.drain() {
+                let _ = reply.send(Err("Order connection closed before a response".to_string()));
+            }
+        }.instrument(span));
+
+        Ok(WsOrderSession { token, requests })
+    }
+
+    async fn place_order_ws(&self, order: &Order) -> Result<(), WsOrderError> {
+        let (volume, _) = self.instruments.order_qty(&self.name, order.volume)
+            .map_err(WsOrderError::Failed)?;
+        let (token, requests) = {
+            let mut session = self.order_session.lock().await;
+            let reusable = session.as_ref().is_some_and(|session| !session.requests.is_closed());
+            if !reusable {
+                *session = Some(self.connect_order_session().await.map_err(WsOrderError::Unavailable)?);
+            }
+            let session = session.as_ref().expect("order session was just connected");
+            (session.token.clone(), session.requests.clone())
+        };
+
+        let symbol = self.order_symbol(order).map_err(WsOrderError::Failed)?;
+        let message = json!({
+            "method": "add_order",
+            "params": {
+                "order_type": "market",
+                "side": match order.side {
+                    OrderSide::Buy => "buy",
+                    OrderSide::Sell => "sell",
+                },
+                "order_qty": volume,
+                "symbol": symbol,
+                "token": token,
+            },
         });
 
+        let (reply, response) = oneshot::channel();
+        requests.send((message, reply))
+            .map_err(|_| WsOrderError::Unavailable("Order connection closed".to_string()))?;
+
+        // Once sent, the order may have reached Kraken, so nothing below retries.
+        match tokio::time::timeout(WS_ORDER_TIMEOUT, response).await {
+            Ok(Ok(Ok(_))) => Ok(()),
+            Ok(Ok(Err(e))) => Err(WsOrderError::Failed(OrderPlaceError::Other(format!("Failed to place order: {}", e)))),
+            Ok(Err(_)) => Err(WsOrderError::Failed(OrderPlaceError::Other("Order connection dropped the request".to_string()))),
+            Err(_) => Err(WsOrderError::Failed(OrderPlaceError::Other("Timed out waiting for order response".to_string()))),
+        }
+    }
+}
+
+    #[async_trait::async_trait]
+    impl Exchange for KrakenExchange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}/{}", instrument.base, instrument.quote))
+    }
+
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
+
+        let subscribe_message = OrderBookSubscribe {
+            method: "subscribe".to_string(),
+            params: OrderBookSubscribeParams {
+                channel: "book".to_string(),
+                symbol: vec![pair],
+                depth: ORDER_BOOK_DEPTH,
+                // A resync relies on the fresh snapshot to rebuild the cleared venue.
+                snapshot: true,
+            },
+        };
+
         let json_message = serde_json::to_string(&subscribe_message)
             .map_err(|e| ExchangeError::SubscriptionFailed(format!("Failed to serialize subscribe message: {}", e)))?;
 
         let (mut socket, _) = connect_async(&self.websocket_url).await?;
         socket.send(Message::Text(json_message.into())).await?;
 
-        self.active.store(true, Ordering::SeqCst);
-
-        let active = Arc::clone(&self.active);
-        let symbol_owned = symbol.to_string();
+        let symbol_owned = instrument.to_string();
         let exchange_name = self.name.clone();
         let sender = self.sender.clone();
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
 
         tokio::spawn(async move {
-            while let Some(result) = socket.next().await {
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
                 match result {
                     Ok(Message::Text(text)) => {
-                        if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
-                            for book_data in ws_msg.data {
-                                for bid in book_data.bids {
-                                    if bid.qty > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Buy,
-                                            price: (bid.price * 100.0) as u64,
-                                            volume: (bid.qty * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
-                                for ask in book_data.asks {
-                                    if ask.qty > 0.0 {
-                                        let ob_order = OBOrder {
-                                            exchange: exchange_name.clone(),
-                                            side: OrderSide::Sell,
-                                            price: (ask.price * 100.0) as u64,
-                                            volume: (ask.qty * 1_000_000.0) as u64,
-                                        };
-                                        if let Err(e) = sender.send(ob_order) {
-                                            eprintln!("Failed to send OBOrder: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        publish(&sender, epoch, parse_book_frame(&text)).await;
                     }
                     Ok(Message::Close(_)) => {
-                        println!("Connection closed");
+                        info!("Connection closed");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
                         break;
                     }
                     Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
+                        error!(error = %e, error_kind = "websocket", "WebSocket error");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
                         break;
                     }
                     _ => {}
                 }
-                if !active.load(Ordering::SeqCst) {
-                    eprintln!("Unsubscribing from order book for {}", symbol_owned);
-                    socket.close(None).await.ok();
-                    active.store(false, Ordering::SeqCst);
+            }
+        }.instrument(span));
+
+        Ok(())
+    }
+
+
+    async fn subscribe_trades(&self, instrument: &Instrument, sender: UnboundedSender<Trade>) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.trade_feed.start();
+
+        let subscribe_message = json!({
+            "method": "subscribe",
+            "params": {
+                "channel": "trade",
+                "symbol": [pair],
+                "snapshot": false,
+            },
+        });
+
+        let (mut socket, _) = connect_async(&self.websocket_url).await?;
+        socket.send(Message::Text(subscribe_message.to_string().into())).await?;
+
+        let symbol_owned = instrument.to_string();
+        let exchange_name = self.name.clone();
+        let metrics = metrics();
+        let span = info_span!("trades", venue = %exchange_name, symbol = %symbol_owned);
+
+        tokio::spawn(async move {
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
                     break;
+                };
+                match result {
+                    Ok(Message::Text(text)) => {
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        for trade in parse_trade_frame(&text, &symbol_owned) {
+                            if sender.send(trade).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("Trade connection closed");
+                        break;
+                    }
+                    Err(e) => {
+                        error!(error = %e, error_kind = "websocket", "Trade websocket error");
+                        break;
+                    }
+                    _ => {}
                 }
             }
-        });
+        }.instrument(span));
 
         Ok(())
     }
 
+    async fn unsubscribe_trades(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.trade_feed.stop() {
+            true => Ok(()),
+            false => Err(ExchangeError::ConnectionClosed),
+        }
+    }
 
-    async fn unsubscribe_ob(&self, _symbol: &str) -> Result<(), ExchangeError> {
-        match self.active.load(Ordering::SeqCst) {
-            true => {
-                self.active.store(false, Ordering::SeqCst);
-                Ok(())
-            }
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
             false => Err(ExchangeError::ConnectionClosed),
         }
     }
-    async fn place_order(&self, _order: Order) -> Result<(), OrderPlaceError> {
-        let nonce = KrakenExchange::get_nonce();
-        let mut params = HashMap::new();
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        // AssetPairs wants the altname, e.g. SOLUSD rather than SOL/USD.
+        let pair = format!("{}{}", instrument.base, instrument.quote);
+        let body = fetch_json(
+            self.client.get(format!("{}/0/public/AssetPairs", self.rest_url)).query(&[("pair", pair.as_str())]),
+        ).await?;
 
-        params.insert("nonce", nonce.as_str());
-        params.insert("ordertype", "market");
-        params.insert("type", match _order.side {
-            OrderSide::Buy => "buy",
-            OrderSide::Sell => "sell",
-        });
-        let volume_str = _order.volume.to_string();
-        params.insert("volume", &volume_str);
-        let pair = format!("{}/USD", _order.symbol);
-        params.insert("pair", &pair);
+        let info = body["result"]
+            .as_object()
+            .and_then(|result| result.values().next())
+            .ok_or_else(|| ExchangeError::InstrumentInfo(format!("{} missing from AssetPairs: {}", pair, body["error"])))?;
+        let lot_decimals = info["lot_decimals"].as_i64().unwrap_or(8) as i32;
 
-        let post_data = serde_urlencoded::to_string(&params)
-            .map_err(OrderPlaceError::Serialization)?;
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: parse_decimal(&info["tick_size"]).unwrap_or(0.0),
+            lot_size: 10f64.powi(-lot_decimals),
+            min_qty: parse_decimal(&info["ordermin"]).unwrap_or(0.0),
+            min_notional: parse_decimal(&info["costmin"]).unwrap_or(0.0),
+        })
+    }
 
-        let signature = KrakenExchange::sign_request(self.order_url.as_str(), &nonce, &post_data, &self.api_secret)?;
+    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError> {
+        match self.place_order_ws(&order).await {
+            Ok(()) => Ok(()),
+            Err(WsOrderError::Unavailable(reason)) => {
+                warn!(reason = %reason, error_kind = "ws_order", "WebSocket order entry unavailable, falling back to REST");
+                self.place_order_rest(&order).await
+            }
+            Err(WsOrderError::Failed(e)) => Err(e),
+        }
+    }
+}
 
-        let res = self.client.post(&self.order_url)
-            .header("API-Key", &self.api_key)
-            .header("API-Sign", signature)
-            .body(post_data)
-            .send()
-            .await
-            .map_err(OrderPlaceError::Http)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
 
-        if res.status().is_success() {
-            Ok(())
-        } else {
-            let error_message = res.text().await.map_err(OrderPlaceError::Http)?;
-            Err(OrderPlaceError::Other(format!("Failed to place order: {}", error_message)))
+    const BOOK_FRAME: &str = r#"{"channel":"book","type":"update","data":[{"symbol":"SOL/USD","bids":[{"price":142.51,"qty":12.5}],"asks":[{"price":142.55,"qty":0.0}],"checksum":2441209245,"timestamp":"2024-05-01T12:00:00.123456Z"}]}"#;
+    const TRADE_FRAME: &str = r#"{"channel":"trade","type":"update","data":[{"symbol":"SOL/USD","side":"buy","price":142.5,"qty":1.25,"ord_type":"market","trade_id":4665906,"timestamp":"2024-05-01T12:00:00.123456Z"}]}"#;
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        #[test]
+        fn book_frames_never_panic(text in frames_like(BOOK_FRAME)) {
+            parse_book_frame(&text);
+        }
+
+        #[test]
+        fn trade_frames_never_panic(text in frames_like(TRADE_FRAME)) {
+            parse_trade_frame(&text, "SOL/USD");
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use tracing::error;
+use crate::channel::BookSender;
+use crate::types::{OBOrder, OrderSide};
+
+/// Tracks which of one venue's price levels are resting in the unified book,
+/// so a fresh snapshot can remove the levels it no longer contains. Turns
+/// level changes into the `OBOrder`s that carry them; sending is left to the feed.
+pub struct LevelTracker {
+    exchange: String,
+    resting: HashSet<(bool, u64)>,
+}
+
+impl LevelTracker {
+    pub fn new(exchange: &str) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            resting: HashSet::new(),
+        }
+    }
+
+    /// Sets the venue's volume at `price`; a zero quantity removes the level.
+    pub fn update(&mut self, side: OrderSide, price: f64, qty: f64, out: &mut Vec<OBOrder>) {
+        let is_bid = matches!(side, OrderSide::Buy);
+        self.push(is_bid, (price * 100.0) as u64, (qty * 1_000_000.0) as u64, out);
+    }
+
+    /// Replaces every level this venue has published with `levels`.
+    pub fn snapshot(&mut self, levels: impl IntoIterator<Item = (OrderSide, f64, f64)>, out: &mut Vec<OBOrder>) {
+        let levels: Vec<(bool, u64, u64)> = levels
+            .into_iter()
+            .map(|(side, price, qty)| {
+                (matches!(side, OrderSide::Buy), (price * 100.0) as u64, (qty * 1_000_000.0) as u64)
+            })
+            .collect();
+
+        let current: HashSet<(bool, u64)> = levels
+            .iter()
+            .filter(|&&(_, _, volume)| volume > 0)
+            .map(|&(is_bid, price, _)| (is_bid, price))
+            .collect();
+        let stale: Vec<(bool, u64)> = self.resting.difference(&current).copied().collect();
+
+        for (is_bid, price) in stale {
+            self.push(is_bid, price, 0, out);
+        }
+        for (is_bid, price, volume) in levels {
+            self.push(is_bid, price, volume, out);
+        }
+    }
+
+    /// Removes every level this venue has published.
+    pub fn clear(&mut self, out: &mut Vec<OBOrder>) {
+        for (is_bid, price) in std::mem::take(&mut self.resting) {
+            out.push(OBOrder::new(
+                self.exchange.clone(),
+                if is_bid { OrderSide::Buy } else { OrderSide::Sell },
+                0,
+                price,
+            ));
+        }
+    }
+
+    fn push(&mut self, is_bid: bool, price: u64, volume: u64, out: &mut Vec<OBOrder>) {
+        if volume > 0 {
+            self.resting.insert((is_bid, price));
+        } else if !self.resting.remove(&(is_bid, price)) {
+            // Removing a level we never published is a no-op for the book.
+            return;
+        }
+
+        out.push(OBOrder::new(
+            self.exchange.clone(),
+            if is_bid { OrderSide::Buy } else { OrderSide::Sell },
+            volume,
+            price,
+        ));
+    }
+}
+
+/// Sends a parsed frame's orders to the unified book, tagged with the
+/// connection's `epoch`.
+pub async fn publish(sender: &BookSender, epoch: u64, orders: Vec<OBOrder>) {
+    for mut ob_order in orders {
+        ob_order.epoch = epoch;
+        if let Err(e) = sender.send(ob_order).await {
+            error!(error = %e, error_kind = "channel", "Failed to send OBOrder");
+        }
+    }
+}
@@ -1,4 +1,11 @@
 pub mod exchange;
 pub mod kraken;
 pub mod alpaca;
-pub mod bybit;
\ No newline at end of file
+pub mod bybit;
+pub mod coinbase;
+pub mod binance;
+pub mod okx;
+pub mod levels;
+pub mod feed;
+#[cfg(test)]
+mod fuzz;
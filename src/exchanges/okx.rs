@@ -0,0 +1,559 @@
+use crate::exchanges::exchange::{Environment, Exchange};
+use crate::errors::{ExchangeError, OrderPlaceError};
+use crate::instruments::{fetch_json, parse_decimal, InstrumentRegistry};
+use crate::channel::BookSender;
+use crate::metrics::metrics;
+use crate::exchanges::feed::FeedLifecycle;
+use crate::exchanges::levels::{publish, LevelTracker};
+use tracing::{error, info, info_span, warn, Instrument as _};
+use crate::types::{Instrument, InstrumentInfo, OBOrder, Order, OrderSide};
+use reqwest::Client;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::connect_async;
+use futures_util::sink::SinkExt;
+use futures_util::stream::StreamExt;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const REST_URL: &str = "https://www.okx.com";
+const ORDER_PATH: &str = "/api/v5/trade/order";
+/// Levels per side that OKX folds into its checksum.
+const CHECKSUM_DEPTH: usize = 25;
+
+const VENUE: &str = "OKX";
+
+/// Quote currencies this adapter can subscribe to.
+const QUOTES: &[&str] = &["USDT", "USDC"];
+
+#[derive(Debug, Deserialize)]
+struct OkxBookMessage {
+    action: String,
+    data: Vec<OkxBookData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OkxBookData {
+    /// `[price, size, deprecated, order count]`.
+    bids: Vec<Vec<String>>,
+    asks: Vec<Vec<String>>,
+    ts: String,
+    checksum: i32,
+    #[serde(rename = "seqId")]
+    seq_id: i64,
+    #[serde(rename = "prevSeqId")]
+    prev_seq_id: i64,
+}
+
+/// Why the local book no longer matches the one OKX is streaming.
+#[derive(Debug, PartialEq)]
+enum BookMismatch {
+    Sequence { expected: i64, received: i64 },
+    Checksum { expected: i32, computed: i32 },
+}
+
+/// OKX's book with the exchange's own price and size strings, which the
+/// checksum is computed over.
+#[derive(Default)]
+struct OkxBook {
+    bids: BTreeMap<u64, (String, String)>,
+    asks: BTreeMap<u64, (String, String)>,
+    /// `None` until a snapshot has been applied.
+    seq_id: Option<i64>,
+}
+
+impl OkxBook {
+    fn key(price: &str) -> Option<u64> {
+        price.parse::<f64>().ok().map(|price| (price * 100_000_000.0).round() as u64)
+    }
+
+    fn apply_levels(levels: &mut BTreeMap<u64, (String, String)>, updates: &[Vec<String>]) {
+        for level in updates {
+            let (Some(price), Some(size)) = (level.first(), level.get(1)) else {
+                continue;
+            };
+            let Some(key) = Self::key(price) else {
+                continue;
+            };
+            if size.parse::<f64>().map_or(true, |size| size == 0.0) {
+                levels.remove(&key);
+            } else {
+                levels.insert(key, (price.clone(), size.clone()));
+            }
+        }
+    }
+
+    /// Applies a `snapshot` or `update` frame, validating its sequence ids
+    /// and checksum.
+    fn apply(&mut self, action: &str, data: &OkxBookData) -> Result<(), BookMismatch> {
+        if action == "snapshot" {
+            self.bids.clear();
+            self.asks.clear();
+        } else {
+            let expected = self.seq_id.unwrap_or(-1);
+            if data.prev_seq_id != expected {
+                return Err(BookMismatch::Sequence { expected, received: data.prev_seq_id });
+            }
+        }
+
+        Self::apply_levels(&mut self.bids, &data.bids);
+        Self::apply_levels(&mut self.asks, &data.asks);
+        self.seq_id = Some(data.seq_id);
+
+        let computed = self.checksum();
+        if computed != data.checksum {
+            return Err(BookMismatch::Checksum { expected: data.checksum, computed });
+        }
+        Ok(())
+    }
+
+    /// The string OKX hashes: best bid and ask levels interleaved as
+    /// `bidPx:bidSz:askPx:askSz:...`, for up to 25 levels a side.
+    fn checksum_input(&self) -> String {
+        let mut bids = self.bids.values().rev().take(CHECKSUM_DEPTH);
+        let mut asks = self.asks.values().take(CHECKSUM_DEPTH);
+        let mut fields = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+
+        for _ in 0..CHECKSUM_DEPTH {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+            for (price, size) in bid.into_iter().chain(ask) {
+                fields.push(price.as_str());
+                fields.push(size.as_str());
+            }
+        }
+        fields.join(":")
+    }
+
+    fn checksum(&self) -> i32 {
+        crc32fast::hash(self.checksum_input().as_bytes()) as i32
+    }
+
+    fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.seq_id = None;
+    }
+}
+
+fn parse_levels(side: OrderSide, levels: &[Vec<String>]) -> impl Iterator<Item = (OrderSide, f64, f64)> + '_ {
+    levels.iter().filter_map(move |level| {
+        let price = level.first()?.parse::<f64>().ok()?;
+        let qty = level.get(1)?.parse::<f64>().ok()?;
+        Some((side.clone(), price, qty))
+    })
+}
+
+/// One connection's view of the `books` channel: the checksummed book plus
+/// the levels it has published. Asks to resubscribe when the book drifts.
+struct OkxFeed {
+    book: OkxBook,
+    levels: LevelTracker,
+    /// Set while waiting for the snapshot of a fresh subscription.
+    resyncing: bool,
+    needs_resubscribe: bool,
+}
+
+impl OkxFeed {
+    fn new() -> Self {
+        Self {
+            book: OkxBook::default(),
+            levels: LevelTracker::new(VENUE),
+            resyncing: false,
+            needs_resubscribe: false,
+        }
+    }
+
+    /// Reads a `books` frame into the orders it carries. Control frames, and
+    /// updates that arrive while resyncing, yield none.
+    fn parse_book_frame(&mut self, text: &str) -> Vec<OBOrder> {
+        let message = match serde_json::from_str::<OkxBookMessage>(text) {
+            Ok(message) => message,
+            Err(e) if text.contains("\"channel\":\"books\"") && text.contains("\"data\"") => {
+                metrics().feed_parse_failures.with_label_values(&[VENUE]).inc();
+                warn!(error = %e, error_kind = "parse", "Failed to parse book frame");
+                return Vec::new();
+            }
+            Err(_) => return Vec::new(),
+        };
+
+        let mut orders = Vec::new();
+        for data in &message.data {
+            if self.resyncing && message.action != "snapshot" {
+                continue;
+            }
+            if let Ok(ts) = data.ts.parse::<i64>() {
+                metrics().observe_feed_latency(VENUE, ts);
+            }
+
+            match self.book.apply(&message.action, data) {
+                Ok(()) => {
+                    self.resyncing = false;
+                    let levels = parse_levels(OrderSide::Buy, &data.bids)
+                        .chain(parse_levels(OrderSide::Sell, &data.asks));
+                    if message.action == "snapshot" {
+                        self.levels.snapshot(levels, &mut orders);
+                    } else {
+                        for (side, price, qty) in levels {
+                            self.levels.update(side, price, qty, &mut orders);
+                        }
+                    }
+                }
+                Err(mismatch) => {
+                    warn!(?mismatch, error_kind = "book_mismatch", "OKX book out of sync, resubscribing");
+                    // The published levels are the ones just proven wrong.
+                    self.levels.clear(&mut orders);
+                    self.book.reset();
+                    self.resyncing = true;
+                    self.needs_resubscribe = true;
+                    break;
+                }
+            }
+        }
+        orders
+    }
+
+    /// True once after the book has drifted and the channel should be resubscribed.
+    fn take_resubscribe_request(&mut self) -> bool {
+        std::mem::take(&mut self.needs_resubscribe)
+    }
+}
+
+pub struct OkxExchange {
+    name: String,
+    api_key: String,
+    api_secret: String,
+    passphrase: String,
+    order_url: String,
+    websocket_url: String,
+    /// Demo trading shares the production REST host and is selected per request.
+    simulated: bool,
+    client: Client,
+    feed: FeedLifecycle,
+    sender: BookSender,
+    fees: f64,
+    instruments: Arc<InstrumentRegistry>,
+}
+
+impl OkxExchange {
+    pub fn new(
+        api_key: String,
+        api_secret: String,
+        passphrase: String,
+        environment: Environment,
+        instruments: Arc<InstrumentRegistry>,
+        sender: BookSender,
+    ) -> Self {
+        let websocket_url = match environment {
+            Environment::Production => "wss://ws.okx.com:8443/ws/v5/public",
+            Environment::Sandbox => "wss://wspap.okx.com:8443/ws/v5/public",
+        };
+        OkxExchange {
+            name: VENUE.to_string(),
+            api_key,
+            api_secret,
+            passphrase,
+            order_url: format!("{}{}", REST_URL, ORDER_PATH),
+            websocket_url: websocket_url.to_string(),
+            simulated: environment == Environment::Sandbox,
+            client: Client::new(),
+            feed: FeedLifecycle::default(),
+            sender,
+            fees: 0.001,
+            instruments,
+        }
+    }
+
+    fn has_credentials(&self) -> bool {
+        !self.api_key.is_empty() && !self.api_secret.is_empty() && !self.passphrase.is_empty()
+    }
+
+    /// Base64 HMAC-SHA256 of `timestamp + method + path + body`.
+    fn sign(&self, timestamp: &str, method: &str, path: &str, body: &str) -> Result<String, OrderPlaceError> {
+        let mut mac = HmacSha256::new_from_slice(self.api_secret.as_bytes())
+            .map_err(|e| OrderPlaceError::HmacError(format!("Failed to create HMAC SHA256 instance: {}", e)))?;
+        mac.update(format!("{}{}{}{}", timestamp, method, path, body).as_bytes());
+        Ok(general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Exchange for OkxExchange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn taker_fee(&self) -> f64 {
+        self.fees
+    }
+
+    fn is_connected(&self) -> bool {
+        self.feed.is_connected()
+    }
+
+    fn venue_symbol(&self, instrument: &Instrument) -> Option<String> {
+        QUOTES
+            .contains(&instrument.quote.as_str())
+            .then(|| format!("{}-{}", instrument.base, instrument.quote))
+    }
+
+    async fn subscribe_ob(&self, instrument: &Instrument, epoch: u64) -> Result<(), ExchangeError> {
+        let pair = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        // Starting first stops the previous connection even if this one fails.
+        let mut token = self.feed.start();
+
+        let args = json!([{ "channel": "books", "instId": pair }]);
+        let subscribe_message = json!({ "op": "subscribe", "args": args }).to_string();
+        let unsubscribe_message = json!({ "op": "unsubscribe", "args": args }).to_string();
+
+        let (mut socket, _) = connect_async(&self.websocket_url).await
+            .map_err(ExchangeError::WebSocketError)?;
+
+        socket.send(Message::Text(subscribe_message.clone().into())).await
+            .map_err(ExchangeError::WebSocketError)?;
+
+        let symbol_owned = instrument.to_string();
+        let exchange_name = self.name.clone();
+        let sender = self.sender.clone();
+        let metrics = metrics();
+        metrics.feed_connects.with_label_values(&[&exchange_name]).inc();
+        let span = info_span!("feed", venue = %exchange_name, symbol = %symbol_owned);
+
+        tokio::spawn(async move {
+            let mut feed = OkxFeed::new();
+
+            loop {
+                let result = tokio::select! {
+                    result = socket.next() => result,
+                    _ = token.stopped() => {
+                        info!("Unsubscribing from order book");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "unsubscribed"]).inc();
+                        socket.close(None).await.ok();
+                        break;
+                    }
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                match result {
+                    Ok(Message::Text(text)) => {
+                        metrics.feed_messages.with_label_values(&[&exchange_name]).inc();
+                        publish(&sender, epoch, feed.parse_book_frame(&text)).await;
+                        if feed.take_resubscribe_request() {
+                            // A new subscription starts with a fresh snapshot.
+                            for message in [&unsubscribe_message, &subscribe_message] {
+                                if let Err(e) = socket.send(Message::Text(message.clone().into())).await {
+                                    error!(error = %e, error_kind = "websocket", "Failed to resubscribe");
+                                }
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("Connection closed");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "closed"]).inc();
+                        break;
+                    }
+                    Err(e) => {
+                        error!(error = %e, error_kind = "websocket", "WebSocket error");
+                        metrics.feed_disconnects.with_label_values(&[exchange_name.as_str(), "error"]).inc();
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }.instrument(span));
+
+        Ok(())
+    }
+
+    async fn unsubscribe_ob(&self, _instrument: &Instrument) -> Result<(), ExchangeError> {
+        match self.feed.stop() {
+            true => Ok(()),
+            false => Err(ExchangeError::ConnectionClosed),
+        }
+    }
+
+    async fn fetch_instrument(&self, instrument: &Instrument) -> Result<InstrumentInfo, ExchangeError> {
+        let inst_id = self.venue_symbol(instrument)
+            .ok_or_else(|| ExchangeError::InvalidSymbol(instrument.to_string()))?;
+        let body = fetch_json(
+            self.client
+                .get(format!("{}/api/v5/public/instruments", REST_URL))
+                .query(&[("instType", "SPOT"), ("instId", inst_id.as_str())]),
+        ).await?;
+
+        let info = &body["data"][0];
+        if info.is_null() {
+            return Err(ExchangeError::InstrumentInfo(format!("{} missing from instruments: {}", inst_id, body["msg"])));
+        }
+
+        // OKX has no minimum notional for spot, only a minimum size.
+        Ok(InstrumentInfo {
+            exchange: self.name.clone(),
+            symbol: instrument.to_string(),
+            tick_size: parse_decimal(&info["tickSz"]).unwrap_or(0.0),
+            lot_size: parse_decimal(&info["lotSz"]).unwrap_or(0.0),
+            min_qty: parse_decimal(&info["minSz"]).unwrap_or(0.0),
+            min_notional: 0.0,
+        })
+    }
+
+    async fn place_order(&self, order: Order) -> Result<(), OrderPlaceError> {
+        let inst_id = self.venue_symbol(&order.instrument)
+            .ok_or_else(|| OrderPlaceError::Other(format!("OKX does not list {}", order.instrument)))?;
+        if !self.has_credentials() {
+            return Err(OrderPlaceError::Other("OKX API credentials are not configured".to_string()));
+        }
+
+        let (_, size) = self.instruments.order_qty(&self.name, order.volume)?;
+        let body = json!({
+            "instId": inst_id,
+            "tdMode": "cash",
+            "side": match order.side {
+                OrderSide::Buy => "buy",
+                OrderSide::Sell => "sell",
+            },
+            "ordType": "market",
+            "sz": size,
+            // Market buys are sized in quote currency unless told otherwise.
+            "tgtCcy": "base_ccy",
+        });
+        let body_str = body.to_string();
+
+        let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let signature = self.sign(&timestamp, "POST", ORDER_PATH, &body_str)?;
+
+        let mut request = self.client.post(&self.order_url);
+        if self.simulated {
+            request = request.header("x-simulated-trading", "1");
+        }
+
+        let res = request
+            .header("OK-ACCESS-KEY", &self.api_key)
+            .header("OK-ACCESS-SIGN", signature)
+            .header("OK-ACCESS-TIMESTAMP", timestamp)
+            .header("OK-ACCESS-PASSPHRASE", &self.passphrase)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body_str)
+            .send()
+            .await
+            .map_err(OrderPlaceError::NetworkError)?;
+
+        if !res.status().is_success() {
+            return Err(OrderPlaceError::Other(
+                format!("Failed to place order, response code: {}", res.status())
+            ));
+        }
+
+        // Rejections come back as 200 with a non-zero `code`.
+        let response: serde_json::Value = res.json().await.map_err(OrderPlaceError::Http)?;
+        if response["code"].as_str() == Some("0") {
+            Ok(())
+        } else {
+            Err(OrderPlaceError::Other(format!("Failed to place order: {}", response["msg"])))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::exchanges::fuzz::{self, frames_like};
+
+    fn level(price: &str, size: &str) -> Vec<String> {
+        vec![price.to_string(), size.to_string(), "0".to_string(), "1".to_string()]
+    }
+
+    fn data(bids: Vec<Vec<String>>, asks: Vec<Vec<String>>, prev_seq_id: i64, seq_id: i64) -> OkxBookData {
+        OkxBookData { bids, asks, ts: "0".to_string(), checksum: 0, seq_id, prev_seq_id }
+    }
+
+    #[test]
+    fn checksum_interleaves_best_levels() {
+        let mut book = OkxBook::default();
+        let snapshot = data(
+            vec![level("3366.1", "7"), level("3366", "6")],
+            vec![level("3366.8", "9"), level("3368", "8"), level("3370", "1")],
+            -1,
+            10,
+        );
+        OkxBook::apply_levels(&mut book.bids, &snapshot.bids);
+        OkxBook::apply_levels(&mut book.asks, &snapshot.asks);
+
+        assert_eq!(book.checksum_input(), "3366.1:7:3366.8:9:3366:6:3368:8:3370:1");
+    }
+
+    #[test]
+    fn rejects_updates_out_of_sequence() {
+        let mut book = OkxBook::default();
+        let mut snapshot = data(vec![level("10", "1")], vec![level("11", "1")], -1, 10);
+        snapshot.checksum = crc32fast::hash(b"10:1:11:1") as i32;
+        book.apply("snapshot", &snapshot).unwrap();
+
+        let mut update = data(vec![level("10", "0")], Vec::new(), 10, 11);
+        update.checksum = crc32fast::hash(b"11:1") as i32;
+        book.apply("update", &update).unwrap();
+
+        let skipped = data(Vec::new(), Vec::new(), 12, 13);
+        assert_eq!(
+            book.apply("update", &skipped),
+            Err(BookMismatch::Sequence { expected: 11, received: 12 }),
+        );
+    }
+
+    fn frame(action: &str, bids: &str, asks: &str, checksum: i32, prev_seq_id: i64, seq_id: i64) -> String {
+        format!(
+            r#"{{"arg":{{"channel":"books","instId":"SOL-USDT"}},"action":"{}","data":[{{"asks":{},"bids":{},"ts":"0","checksum":{},"prevSeqId":{},"seqId":{}}}]}}"#,
+            action, asks, bids, checksum, prev_seq_id, seq_id,
+        )
+    }
+
+    #[test]
+    fn checksum_mismatch_clears_published_levels() {
+        let mut feed = OkxFeed::new();
+        let snapshot = frame("snapshot", r#"[["10","1","0","1"]]"#, r#"[["11","1","0","1"]]"#, crc32fast::hash(b"10:1:11:1") as i32, -1, 10);
+        assert_eq!(feed.parse_book_frame(&snapshot).len(), 2);
+
+        let corrupt = frame("update", r#"[["10","2","0","1"]]"#, "[]", 0, 10, 11);
+        let orders = feed.parse_book_frame(&corrupt);
+        let mut removed: Vec<(bool, u64, u64)> = orders
+            .iter()
+            .map(|order| (matches!(order.side, OrderSide::Buy), order.price, order.volume))
+            .collect();
+        removed.sort();
+        assert_eq!(removed, vec![(false, 1_100, 0), (true, 1_000, 0)]);
+        assert!(feed.take_resubscribe_request());
+
+        // Updates are ignored until the fresh snapshot arrives.
+        let update = frame("update", r#"[["10","3","0","1"]]"#, "[]", 0, 11, 12);
+        assert!(feed.parse_book_frame(&update).is_empty());
+    }
+
+    const BOOK_FRAME: &str = r#"{"arg":{"channel":"books","instId":"SOL-USDT"},"action":"update","data":[{"asks":[["142.55","3","0","2"]],"bids":[["142.51","12.5","0","4"],["142.5","0","0","0"]],"ts":"1714564800123","checksum":-855196043,"prevSeqId":123456,"seqId":123457}]}"#;
+
+    proptest! {
+        #![proptest_config(fuzz::config())]
+
+        /// Runs fuzzed frames through the feed's parser, which resets the
+        /// book whenever a frame does not follow on.
+        #[test]
+        fn book_frames_never_panic(frames in prop::collection::vec(frames_like(BOOK_FRAME), 1..8)) {
+            let mut feed = OkxFeed::new();
+            for text in frames {
+                feed.parse_book_frame(&text);
+                feed.take_resubscribe_request();
+            }
+        }
+    }
+}
+
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use crate::errors::{ExchangeError, OrderPlaceError};
+use crate::types::InstrumentInfo;
+
+/// Instrument rules per venue, filled in as each adapter's metadata arrives
+/// and shared by the router and the adapters' `place_order`.
+#[derive(Default)]
+pub struct InstrumentRegistry {
+    instruments: RwLock<HashMap<String, InstrumentInfo>>,
+}
+
+impl InstrumentRegistry {
+    pub fn insert(&self, info: InstrumentInfo) {
+        self.instruments
+            .write()
+            .expect("instrument registry poisoned")
+            .insert(info.exchange.clone(), info);
+    }
+
+    pub fn get(&self, exchange: &str) -> Option<InstrumentInfo> {
+        self.instruments
+            .read()
+            .expect("instrument registry poisoned")
+            .get(exchange)
+            .cloned()
+    }
+
+    /// Rounds `qty` down to the venue's lot size, checks it against the
+    /// minimum size, and formats it with the venue's quantity precision.
+    /// Returns the quantity both as a number and as the formatted decimal,
+    /// which always agree.
+    pub fn order_qty(&self, exchange: &str, qty: f64) -> Result<(f64, String), OrderPlaceError> {
+        let info = self.get(exchange).ok_or_else(|| {
+            OrderPlaceError::InvalidQuantity(format!("no instrument info loaded for {}", exchange))
+        })?;
+
+        let rounded = info.round_qty(qty);
+        info.check(rounded, None).map_err(OrderPlaceError::InvalidQuantity)?;
+        let formatted = info.format_qty(rounded);
+        Ok((formatted.parse().unwrap_or(rounded), formatted))
+    }
+}
+
+impl InstrumentInfo {
+    /// Rounds down to a whole number of lots, so an order never exceeds `qty`.
+    pub fn round_qty(&self, qty: f64) -> f64 {
+        if self.lot_size <= 0.0 {
+            return qty;
+        }
+        // The small epsilon stops 0.3 / 0.1 from flooring to 2 lots.
+        let lots = (qty / self.lot_size + 1e-9).floor();
+        lots * self.lot_size
+    }
+
+    /// Checks `qty` against the venue minimums; the notional check needs a price.
+    pub fn check(&self, qty: f64, price: Option<f64>) -> Result<(), String> {
+        if qty <= 0.0 || qty < self.min_qty {
+            return Err(format!("{} is below the {} minimum of {}", qty, self.exchange, self.min_qty));
+        }
+        if let Some(price) = price {
+            let notional = qty * price;
+            if notional < self.min_notional {
+                return Err(format!(
+                    "notional {:.2} is below the {} minimum of {}",
+                    notional, self.exchange, self.min_notional
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn format_qty(&self, qty: f64) -> String {
+        format!("{:.*}", decimals(self.lot_size), qty)
+    }
+}
+
+/// Decimal places needed to write `step`, e.g. 3 for 0.001.
+fn decimals(step: f64) -> usize {
+    let formatted = step.to_string();
+    formatted.split_once('.').map_or(0, |(_, fraction)| fraction.trim_end_matches('0').len())
+}
+
+/// Reads a decimal that venues send either as a JSON string or a number.
+pub fn parse_decimal(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::String(s) => s.parse().ok(),
+        serde_json::Value::Number(n) => n.as_f64(),
+        _ => None,
+    }
+}
+
+/// Sends a metadata request and returns its JSON body.
+pub async fn fetch_json(request: reqwest::RequestBuilder) -> Result<serde_json::Value, ExchangeError> {
+    let res = request
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| ExchangeError::InstrumentInfo(e.to_string()))?;
+    res.json().await.map_err(|e| ExchangeError::InstrumentInfo(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> InstrumentInfo {
+        InstrumentInfo {
+            exchange: "Kraken".to_string(),
+            symbol: "SOL".to_string(),
+            tick_size: 0.01,
+            lot_size: 0.001,
+            min_qty: 0.02,
+            min_notional: 5.0,
+        }
+    }
+
+    #[test]
+    fn rounds_down_to_lot_and_checks_minimums() {
+        let registry = InstrumentRegistry::default();
+        registry.insert(info());
+
+        assert_eq!(registry.order_qty("Kraken", 1.23456).unwrap(), (1.234, "1.234".to_string()));
+        assert_eq!(registry.order_qty("Kraken", 0.3).unwrap(), (0.3, "0.300".to_string()));
+        assert!(registry.order_qty("Kraken", 0.0199).is_err());
+        assert!(registry.order_qty("Bybit", 1.0).is_err());
+
+        assert!(info().check(0.03, Some(100.0)).is_err());
+        assert!(info().check(0.05, Some(100.0)).is_ok());
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+use prometheus::{
+    CounterVec, Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Process-wide Prometheus collectors, served as text by `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// Text frames received per venue, including control messages.
+    pub feed_messages: IntCounterVec,
+    /// Book frames per venue that failed to deserialize.
+    pub feed_parse_failures: IntCounterVec,
+    /// Successful websocket connections per venue.
+    pub feed_connects: IntCounterVec,
+    /// Feed terminations per venue, labelled by `reason`.
+    pub feed_disconnects: IntCounterVec,
+    /// Exchange timestamp to local receipt, per venue.
+    pub feed_latency: HistogramVec,
+    /// Price levels currently held per side of the unified book.
+    pub book_levels: IntGaugeVec,
+    /// Levels dropped per venue for falling outside the depth cap.
+    pub book_pruned_levels: IntCounterVec,
+    /// Messages queued but not yet consumed, per internal channel.
+    pub channel_backlog: IntGaugeVec,
+    /// Book updates discarded by a full `drop-oldest` channel.
+    pub channel_dropped: IntCounterVec,
+    /// Book updates merged into a queued update by a full `coalesce` channel.
+    pub channel_coalesced: IntCounterVec,
+    /// Bridged book updates dropped per venue for want of a current conversion rate.
+    pub conversion_dropped: IntCounterVec,
+    /// Time spent walking the book for a quote.
+    pub quote_duration: Histogram,
+    /// Base volume printed in public trades, per venue and aggressor side.
+    pub trade_volume: CounterVec,
+    /// Base volume within each analytics band of mid, per venue and side.
+    pub liquidity_depth: GaugeVec,
+    /// Bid against ask volume within each analytics band, from -1 to 1.
+    pub book_imbalance: GaugeVec,
+    /// Consolidated VWAP distance from mid for each analytics size.
+    pub price_impact_bps: GaugeVec,
+    /// What filling each analytics size across venues saves over one venue alone.
+    pub aggregation_savings_bps: GaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("blockfinders".to_string()), None)
+            .expect("valid metrics namespace");
+
+        let feed_messages = IntCounterVec::new(
+            Opts::new("feed_messages_total", "Websocket text frames received"),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let feed_parse_failures = IntCounterVec::new(
+            Opts::new("feed_parse_failures_total", "Book frames that failed to parse"),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let feed_connects = IntCounterVec::new(
+            Opts::new("feed_connects_total", "Websocket connections established"),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let feed_disconnects = IntCounterVec::new(
+            Opts::new("feed_disconnects_total", "Websocket feeds that stopped"),
+            &["venue", "reason"],
+        )
+        .expect("valid metric");
+        let feed_latency = HistogramVec::new(
+            HistogramOpts::new("feed_latency_seconds", "Exchange timestamp to local receipt")
+                .buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let book_levels = IntGaugeVec::new(
+            Opts::new("book_levels", "Price levels in the unified book"),
+            &["side"],
+        )
+        .expect("valid metric");
+        let book_pruned_levels = IntCounterVec::new(
+            Opts::new("book_pruned_levels_total", "Levels pruned beyond the per-venue depth cap"),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let channel_backlog = IntGaugeVec::new(
+            Opts::new("channel_backlog", "Messages waiting in an internal channel"),
+            &["channel"],
+        )
+        .expect("valid metric");
+        let channel_dropped = IntCounterVec::new(
+            Opts::new("channel_dropped_total", "Book updates dropped by a full channel"),
+            &["channel"],
+        )
+        .expect("valid metric");
+        let channel_coalesced = IntCounterVec::new(
+            Opts::new("channel_coalesced_total", "Book updates coalesced by a full channel"),
+            &["channel"],
+        )
+        .expect("valid metric");
+        let conversion_dropped = IntCounterVec::new(
+            Opts::new("conversion_dropped_total", "Bridged book updates dropped without a current conversion rate"),
+            &["venue"],
+        )
+        .expect("valid metric");
+        let quote_duration = Histogram::with_opts(
+            HistogramOpts::new("quote_duration_seconds", "Time to compute a quote from the book")
+                .buckets(vec![0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.01]),
+        )
+        .expect("valid metric");
+
+        let trade_volume = CounterVec::new(
+            Opts::new("trade_volume_total", "Base volume traded on each venue's public tape"),
+            &["venue", "side"],
+        )
+        .expect("valid metric");
+
+        let liquidity_depth = GaugeVec::new(
+            Opts::new("liquidity_depth", "Base volume within a band of mid"),
+            &["venue", "side", "band_bps"],
+        )
+        .expect("valid metric");
+        let book_imbalance = GaugeVec::new(
+            Opts::new("book_imbalance", "Bid minus ask volume over their sum within a band of mid"),
+            &["band_bps"],
+        )
+        .expect("valid metric");
+        let price_impact_bps = GaugeVec::new(
+            Opts::new("price_impact_bps", "Consolidated VWAP distance from mid for an order size"),
+            &["side", "size"],
+        )
+        .expect("valid metric");
+        let aggregation_savings_bps = GaugeVec::new(
+            Opts::new("aggregation_savings_bps", "Consolidated VWAP improvement over a single venue"),
+            &["venue", "side", "size"],
+        )
+        .expect("valid metric");
+
+        registry.register(Box::new(feed_messages.clone())).expect("unique metric");
+        registry.register(Box::new(feed_parse_failures.clone())).expect("unique metric");
+        registry.register(Box::new(feed_connects.clone())).expect("unique metric");
+        registry.register(Box::new(feed_disconnects.clone())).expect("unique metric");
+        registry.register(Box::new(feed_latency.clone())).expect("unique metric");
+        registry.register(Box::new(book_levels.clone())).expect("unique metric");
+        registry.register(Box::new(book_pruned_levels.clone())).expect("unique metric");
+        registry.register(Box::new(channel_backlog.clone())).expect("unique metric");
+        registry.register(Box::new(channel_dropped.clone())).expect("unique metric");
+        registry.register(Box::new(channel_coalesced.clone())).expect("unique metric");
+        registry.register(Box::new(conversion_dropped.clone())).expect("unique metric");
+        registry.register(Box::new(quote_duration.clone())).expect("unique metric");
+        registry.register(Box::new(trade_volume.clone())).expect("unique metric");
+        registry.register(Box::new(liquidity_depth.clone())).expect("unique metric");
+        registry.register(Box::new(book_imbalance.clone())).expect("unique metric");
+        registry.register(Box::new(price_impact_bps.clone())).expect("unique metric");
+        registry.register(Box::new(aggregation_savings_bps.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            feed_messages,
+            feed_parse_failures,
+            feed_connects,
+            feed_disconnects,
+            feed_latency,
+            book_levels,
+            book_pruned_levels,
+            channel_backlog,
+            channel_dropped,
+            channel_coalesced,
+            conversion_dropped,
+            quote_duration,
+            trade_volume,
+            liquidity_depth,
+            book_imbalance,
+            price_impact_bps,
+            aggregation_savings_bps,
+        }
+    }
+
+    /// Records feed latency from an exchange timestamp in Unix milliseconds.
+    /// Timestamps ahead of the local clock are ignored.
+    pub fn observe_feed_latency(&self, venue: &str, exchange_time_ms: i64) {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis() as i64;
+        let latency_ms = now_ms.saturating_sub(exchange_time_ms);
+        if latency_ms >= 0 {
+            self.feed_latency
+                .with_label_values(&[venue])
+                .observe(latency_ms as f64 / 1000.0);
+        }
+    }
+
+    /// Records feed latency from an RFC 3339 exchange timestamp.
+    pub fn observe_feed_latency_rfc3339(&self, venue: &str, timestamp: &str) {
+        if let Ok(time) = chrono::DateTime::parse_from_rfc3339(timestamp) {
+            self.observe_feed_latency(venue, time.timestamp_millis());
+        }
+    }
+
+    /// Prometheus text exposition of every collector.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!(error = %e, error_kind = "metrics", "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -1,163 +1,531 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
 };
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use crate::types::{OBOrder, OrderRequest, OrderSide, PriceResponse};
-struct SideOrderBook {
-    orders: BTreeMap<u64, VecDeque<OBOrder>>,
-    query_receiver: UnboundedReceiver<OrderRequest>,
-    receiver: UnboundedReceiver<OBOrder>,
-    active: Arc<AtomicBool>,
-    pause: Arc<AtomicBool>,
-    is_buy: bool
+use tokio::sync::{
+    broadcast,
+    mpsc::{channel, Receiver, Sender},
+    oneshot, watch,
+};
+use crate::channel::BookReceiver;
+use crate::types::{
+    BookSnapshot, FillSlice, LevelUpdate, OBOrder, OrderRequest, OrderSide, PriceLevel, PriceResponse, QuoteDetail,
+    SequencedBookSnapshot, VenueLevel, VenueQuote, VenueSequences, VenueTops, VolumeUnit,
+};
+
+/// Level updates a slow subscriber may fall behind by before it is told it lagged.
+const UPDATE_BROADCAST_CAPACITY: usize = 4096;
+/// Updates handed to the book writer but not yet applied. The dispatcher waits
+/// when it is full, so overload is handled by the policy on the main channel.
+const WRITER_CHANNEL_CAPACITY: usize = 1024;
+/// Commands waiting for the book writer.
+const COMMAND_CHANNEL_CAPACITY: usize = 16;
+
+/// Most updates the writer applies before publishing a new snapshot, so
+/// readers still see progress while a long backlog drains.
+const MAX_UPDATE_BATCH: usize = 256;
+
+enum BookCommand {
+    /// Removes every level a venue has on either side and replies with the
+    /// venue's new epoch.
+    ClearVenue(String, oneshot::Sender<u64>),
+}
+
+/// Index of a venue in `BookState::venues`, assigned the first time the
+/// venue sends an update or is cleared.
+type VenueId = u16;
+
+/// Volume resting at one price, keyed by venue id.
+#[derive(Clone, Default)]
+struct Level {
+    /// Volume per venue id; zero where the venue has nothing at this price.
+    volumes: Vec<u64>,
+    /// Venues with volume here in the order they joined the level, which is
+    /// the order a quote fills them in.
+    queue: Vec<VenueId>,
+    /// Sum of `volumes`, kept up to date so depth reads need not re-add it.
+    total_volume: u64,
+    /// Rate per venue id that its price was converted at, zero where it was
+    /// not; empty until a converted venue joins the level.
+    rates: Vec<f64>,
+}
+
+impl Level {
+    /// Sets a venue's volume here, zero removing it, along with the rate the
+    /// venue's price was converted at. Returns whether the level changed.
+    fn set(&mut self, venue: VenueId, volume: u64, rate: Option<f64>) -> bool {
+        let slot = venue as usize;
+        if let Some(rate) = rate.filter(|_| volume > 0) {
+            if slot >= self.rates.len() {
+                self.rates.resize(slot + 1, 0.0);
+            }
+            self.rates[slot] = rate;
+        }
+        if slot >= self.volumes.len() {
+            if volume == 0 {
+                return false;
+            }
+            self.volumes.resize(slot + 1, 0);
+        }
+
+        let previous = std::mem::replace(&mut self.volumes[slot], volume);
+        if previous == volume {
+            return false;
+        }
+        self.total_volume = self.total_volume - previous + volume;
+        if previous == 0 {
+            self.queue.push(venue);
+        } else if volume == 0 {
+            self.queue.retain(|&queued| queued != venue);
+        }
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Rate the venue's price here was converted at, if it was.
+    fn rate(&self, venue: VenueId) -> Option<f64> {
+        self.rates.get(venue as usize).copied().filter(|&rate| rate > 0.0)
+    }
+
+    /// Each venue's volume, in fill order.
+    fn iter(&self) -> impl Iterator<Item = (VenueId, u64)> + '_ {
+        self.queue.iter().map(|&venue| (venue, self.volumes[venue as usize]))
+    }
+}
+
+/// One side of the book.
+#[derive(Clone)]
+struct SideLevels {
+    orders: BTreeMap<u64, Level>,
+    is_buy: bool,
+    /// Number of level changes applied so far; stamped on every published update.
+    sequence: u64,
+}
+
+/// Both sides of the book as of the same update. The writer publishes a new
+/// state after each batch, and readers walk whichever one they loaded without
+/// holding any lock, so a quote never waits behind queued updates and a
+/// two-sided read never pairs bids and asks from different moments.
+#[derive(Clone)]
+struct BookState {
+    bids: Arc<SideLevels>,
+    asks: Arc<SideLevels>,
+    /// Venue names by `VenueId`.
+    venues: Arc<Vec<String>>,
+    /// Updates applied per venue id across both sides, identifying the point
+    /// in each venue's feed this state reflects.
+    venue_sequences: Vec<u64>,
+    /// Number of updates processed, whether or not they changed a level.
+    applied: u64,
+    /// False once the writer has stopped.
+    active: bool,
+}
+
+impl BookState {
+    fn side(&self, side: &OrderSide) -> &SideLevels {
+        match side {
+            OrderSide::Buy => &self.bids,
+            OrderSide::Sell => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, is_buy: bool) -> &mut SideLevels {
+        Arc::make_mut(if is_buy { &mut self.bids } else { &mut self.asks })
+    }
+
+    fn venue_sequences(&self) -> VenueSequences {
+        self.venues.iter().cloned().zip(self.venue_sequences.iter().copied()).collect()
+    }
+}
+
+/// Prices a venue currently has volume at, per side.
+#[derive(Default)]
+struct VenuePrices {
+    bids: BTreeSet<u64>,
+    asks: BTreeSet<u64>,
+}
+
+/// A level change applied to the state being written, kept so the spare
+/// state can replay it.
+struct LevelChange {
+    is_buy: bool,
+    venue: VenueId,
+    price: u64,
+    volume: u64,
+    rate: Option<f64>,
+}
+
+/// The only task that mutates the book. It double-buffers the state: the
+/// watch channel always holds the last published state, so rather than
+/// cloning it to write the next batch, the writer switches to the state it
+/// published before that and replays the changes it missed. A side is only
+/// cloned when a reader still holds that older state when the batch starts.
+struct BookWriter {
+    /// The state being written; published after each batch.
+    state: Arc<BookState>,
+    /// The state published before `state`, and the level changes applied to
+    /// `state` since.
+    spare: Option<(Arc<BookState>, Vec<LevelChange>)>,
+    venue_ids: HashMap<String, VenueId>,
+    /// Indexed by `VenueId`, for enforcing `max_depth` and clearing a venue.
+    venue_prices: Vec<VenuePrices>,
+    /// Indexed by `VenueId`: bumped on every clear, so updates queued before
+    /// it, which carry an older epoch, cannot bring cleared levels back.
+    venue_epochs: Vec<u64>,
+    /// Levels kept per venue and side; worse ones are pruned.
+    max_depth: usize,
+    published: watch::Sender<Arc<BookState>>,
+    command_receiver: Receiver<BookCommand>,
+    receiver: Receiver<OBOrder>,
+    updates: broadcast::Sender<LevelUpdate>,
 }
 use crate::errors::OrderBookError;
+use crate::metrics::metrics;
+use std::time::Instant;
+use tracing::{debug, instrument};
 
 
-impl SideOrderBook {
-    fn new(query_receiver: UnboundedReceiver<OrderRequest>, receiver: UnboundedReceiver<OBOrder>, is_buy: bool) -> Self {
-        Self {
+impl BookWriter {
+    fn new(
+        max_depth: usize,
+        command_receiver: Receiver<BookCommand>,
+        receiver: Receiver<OBOrder>,
+        updates: broadcast::Sender<LevelUpdate>,
+    ) -> (Self, watch::Receiver<Arc<BookState>>) {
+        let side = |is_buy| Arc::new(SideLevels {
             orders: BTreeMap::new(),
-            query_receiver,
-            receiver,
-            active: Arc::new(AtomicBool::new(true)),
-            pause: Arc::new(AtomicBool::new(false)),    
             is_buy,
-        }
+            sequence: 0,
+        });
+        let state = Arc::new(BookState {
+            bids: side(true),
+            asks: side(false),
+            venues: Arc::new(Vec::new()),
+            venue_sequences: Vec::new(),
+            applied: 0,
+            active: true,
+        });
+        let (published, snapshots) = watch::channel(state.clone());
+        let writer = Self {
+            state,
+            spare: None,
+            venue_ids: HashMap::new(),
+            venue_prices: Vec::new(),
+            venue_epochs: Vec::new(),
+            max_depth: max_depth.max(1),
+            published,
+            command_receiver,
+            receiver,
+            updates,
+        };
+        (writer, snapshots)
     }
 
     async fn run(mut self) {
-        while self.active.load(Ordering::SeqCst) {
-            while self.pause.load(Ordering::SeqCst) {
-                tokio::task::yield_now().await;
-            }
-
+        loop {
             tokio::select! {
-                Some(order) = self.receiver.recv() => {
-                    self.process_order(order);
+                biased;
+
+                Some(command) = self.command_receiver.recv() => {
+                    self.begin_batch();
+                    match command {
+                        BookCommand::ClearVenue(exchange, reply) => {
+                            let epoch = self.clear_venue(&exchange);
+                            self.publish_snapshot();
+                            let _ = reply.send(epoch);
+                        }
+                    }
                 }
 
-                Some(order_request) = self.query_receiver.recv() => {
-                    if let Ok(response) = self.get_best_quote(order_request) {
-                        println!("Best quote: {:?}", response);
-                    } else {
-                        println!("Failed to get best quote");
+                Some(order) = self.receiver.recv() => {
+                    self.begin_batch();
+                    self.process_order(order);
+                    let mut batch = 1;
+                    while batch < MAX_UPDATE_BATCH {
+                        match self.receiver.try_recv() {
+                            Ok(order) => self.process_order(order),
+                            Err(_) => break,
+                        }
+                        batch += 1;
                     }
+                    Arc::make_mut(&mut self.state).applied += batch as u64;
+                    self.publish_snapshot();
                 }
 
-                else => {
-                    self.active.store(false, Ordering::SeqCst);
-                    break;
+                else => break,
+            }
+        }
+
+        self.begin_batch();
+        Arc::make_mut(&mut self.state).active = false;
+        self.publish_snapshot();
+    }
+
+    /// Makes `state` writable without cloning it where possible. `state` was
+    /// just published, so the writer switches to the spare state once no
+    /// reader holds it, replaying the changes it missed, and keeps the
+    /// published one as the next spare.
+    fn begin_batch(&mut self) {
+        if let Some((mut spare, mut missed)) = self.spare.take() {
+            if let Some(writable) = Arc::get_mut(&mut spare) {
+                for change in missed.drain(..) {
+                    writable.side_mut(change.is_buy).apply(change.venue, change.price, change.volume, change.rate);
                 }
+                writable.venues = self.state.venues.clone();
+                writable.venue_sequences.clone_from(&self.state.venue_sequences);
+                writable.applied = self.state.applied;
+                writable.active = self.state.active;
+                self.spare = Some((std::mem::replace(&mut self.state, spare), missed));
+                return;
             }
         }
+        // A reader still holds the spare, or there is none yet: writing to
+        // `state` clones what the batch touches, and the published state
+        // becomes the spare.
+        self.spare = Some((self.state.clone(), Vec::new()));
+    }
+
+    fn publish_snapshot(&self) {
+        self.published.send_replace(self.state.clone());
+
+        let metrics = metrics();
+        metrics.book_levels.with_label_values(&["bids"]).set(self.state.bids.orders.len() as i64);
+        metrics.book_levels.with_label_values(&["asks"]).set(self.state.asks.orders.len() as i64);
+        metrics.channel_backlog.with_label_values(&["book"]).set(self.receiver.len() as i64);
+    }
+
+    fn venue_id(&mut self, exchange: &str) -> VenueId {
+        if let Some(&venue) = self.venue_ids.get(exchange) {
+            return venue;
+        }
+        let state = Arc::make_mut(&mut self.state);
+        let venue = state.venues.len() as VenueId;
+        Arc::make_mut(&mut state.venues).push(exchange.to_string());
+        state.venue_sequences.push(0);
+        self.venue_prices.push(VenuePrices::default());
+        self.venue_epochs.push(0);
+        self.venue_ids.insert(exchange.to_string(), venue);
+        venue
     }
 
+    /// Removes the venue's levels and starts its next epoch, which it returns.
+    fn clear_venue(&mut self, exchange: &str) -> u64 {
+        let venue = self.venue_id(exchange);
+        self.venue_epochs[venue as usize] += 1;
+        let prices = std::mem::take(&mut self.venue_prices[venue as usize]);
+        for price in prices.bids {
+            self.apply(true, venue, price, 0, None);
+        }
+        for price in prices.asks {
+            self.apply(false, venue, price, 0, None);
+        }
+        self.venue_epochs[venue as usize]
+    }
 
     fn process_order(&mut self, order: OBOrder) {
-        let price = order.price;
-        if let Some(queue) = self.orders.get_mut(&order.price) {
-            let mut i = 0;
-            let mut found = false;
-            while i < queue.len() {
-                if queue[i].exchange == order.exchange {
-                    found = true;
-                    if order.volume == 0 {
-                        queue.remove(i);
-                    } else {
-                        queue[i].volume = order.volume;
-                    }
-                    break;
+        let venue = self.venue_id(&order.exchange);
+        if order.epoch < self.venue_epochs[venue as usize] {
+            return;
+        }
+        Arc::make_mut(&mut self.state).venue_sequences[venue as usize] += 1;
+        let is_buy = matches!(order.side, OrderSide::Buy);
+        self.apply(is_buy, venue, order.price, order.volume, order.rate);
+        self.prune(is_buy, venue, order.price, order.volume);
+    }
+
+    /// Drops the venue's worst level on this side once a new price takes it
+    /// past `max_depth`. Levels pruned here are not restored when the venue
+    /// later removes a better one; it refills as the venue sends updates.
+    fn prune(&mut self, is_buy: bool, venue: VenueId, price: u64, volume: u64) {
+        let prices = &mut self.venue_prices[venue as usize];
+        let prices = if is_buy { &mut prices.bids } else { &mut prices.asks };
+        if volume == 0 {
+            prices.remove(&price);
+            return;
+        }
+        if !prices.insert(price) || prices.len() <= self.max_depth {
+            return;
+        }
+
+        let worst = if is_buy { prices.pop_first() } else { prices.pop_last() };
+        if let Some(worst) = worst {
+            self.apply(is_buy, venue, worst, 0, None);
+            metrics().book_pruned_levels.with_label_values(&[&self.state.venues[venue as usize]]).inc();
+        }
+    }
+
+    fn apply(&mut self, is_buy: bool, venue: VenueId, price: u64, volume: u64, rate: Option<f64>) {
+        if let Some((_, missed)) = &mut self.spare {
+            missed.push(LevelChange { is_buy, venue, price, volume, rate });
+        }
+        let state = Arc::make_mut(&mut self.state);
+        let side = state.side_mut(is_buy);
+        if !side.apply(venue, price, volume, rate) {
+            return;
+        }
+        // Only name the venue when someone is listening; sending to nobody is fine.
+        if self.updates.receiver_count() > 0 {
+            let _ = self.updates.send(LevelUpdate {
+                side: if is_buy { OrderSide::Buy } else { OrderSide::Sell },
+                sequence: side.sequence,
+                exchange: state.venues[venue as usize].clone(),
+                price: price as f64 / 100.0,
+                volume: volume as f64 / 1_000_000.0,
+            });
+        }
+    }
+}
+
+impl SideLevels {
+    /// Sets one venue's volume at a price. Returns whether the book changed,
+    /// in which case the side's sequence has advanced.
+    fn apply(&mut self, venue: VenueId, price: u64, volume: u64, rate: Option<f64>) -> bool {
+        let changed = match self.orders.get_mut(&price) {
+            Some(level) => {
+                let changed = level.set(venue, volume, rate);
+                if level.is_empty() {
+                    self.orders.remove(&price);
                 }
-                i += 1;
+                changed
             }
-            if !found && order.volume != 0 {
-                queue.push_back(order);
+            None if volume > 0 => {
+                let mut level = Level::default();
+                level.set(venue, volume, rate);
+                self.orders.insert(price, level);
+                true
             }
-        } else if order.volume != 0 {
-            let mut queue = VecDeque::new();
-            queue.push_back(order);
-            self.orders.insert(price, queue);
+            None => false,
+        };
+        if changed {
+            self.sequence += 1;
         }
+        changed
+    }
+
+    fn best_price(&self) -> Option<u64> {
+        self.levels().next().map(|(&price, _)| price)
+    }
 
-        if self.orders.get(&price).map_or(false, |queue| queue.is_empty()) {
-            self.orders.remove(&price);
+    /// Price levels ordered from the best price outwards.
+    fn levels(&self) -> Box<dyn Iterator<Item = (&u64, &Level)> + '_> {
+        if self.is_buy {
+            Box::new(self.orders.iter().rev())
+        } else {
+            Box::new(self.orders.iter())
         }
     }
 
-    pub fn get_best_quote(&self, order: OrderRequest) -> Result<PriceResponse, OrderBookError> {
-        if !self.active.load(Ordering::SeqCst) {
-            return Err(OrderBookError::InactiveOrderBook);
+    fn get_depth(&self, venues: &[String], depth: usize) -> Vec<PriceLevel> {
+        self.levels()
+            .take(depth)
+            .map(|(&price, level)| PriceLevel {
+                price: price as f64 / 100.0,
+                total_volume: level.total_volume as f64 / 1_000_000.0,
+                venues: level
+                    .iter()
+                    .map(|(venue, volume)| VenueLevel {
+                        exchange: venues[venue as usize].clone(),
+                        volume: volume as f64 / 1_000_000.0,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Best price each venue is currently showing on this side.
+    fn get_venue_tops(&self, venues: &[String]) -> Vec<VenueQuote> {
+        let mut seen = vec![false; venues.len()];
+        let mut tops: Vec<VenueQuote> = Vec::new();
+        for (&price, level) in self.levels() {
+            for (venue, volume) in level.iter() {
+                if !std::mem::replace(&mut seen[venue as usize], true) {
+                    tops.push(VenueQuote {
+                        exchange: venues[venue as usize].clone(),
+                        price: price as f64 / 100.0,
+                        volume: volume as f64 / 1_000_000.0,
+                    });
+                }
+            }
+            if tops.len() == venues.len() {
+                break;
+            }
         }
+        tops
+    }
 
-        self.pause.store(true, Ordering::SeqCst);
+    /// Walks this side for `order`. `venue_quotes` names the quote currency
+    /// of each venue whose prices were converted, to report the rates used.
+    fn get_best_quote(
+        &self,
+        venues: &[String],
+        venue_quotes: &HashMap<String, String>,
+        order: OrderRequest,
+    ) -> Result<PriceResponse, OrderBookError> {
+        if self.orders.is_empty() {
+            return Err(OrderBookError::EmptyOrderBook);
+        }
 
-        let formatted_volume = (order.volume * 1_000_000.0) as u64; 
+        let started = Instant::now();
+
+        // Base targets count volume units; quote targets count price x volume.
+        let target = match order.unit {
+            VolumeUnit::Base => (order.volume * 1_000_000.0) as u128,
+            VolumeUnit::Quote => (order.volume * 100_000_000.0) as u128,
+        };
         let symbol = order.symbol.clone();
+        let side = order.side.clone();
 
         let mut total_volume = 0u64;
-        let mut alpaca_volume = 0u64;
-        let mut kraken_volume = 0u64;
-        let mut bybit_volume = 0u64;
+        let mut venue_volumes = vec![0u64; venues.len()];
         let mut weighted_price_sum = 0u128; 
+        let mut worst_price = 0u64;
+        let mut slices = Vec::new();
+        let mut fully_filled = false;
+        // Rate-weighted volume and volume taken per bridged quote currency.
+        let mut converted: BTreeMap<&str, (f64, u64)> = BTreeMap::new();
 
-        if self.is_buy {
-            for (&price, queue) in self.orders.iter().rev() {
-                for order in queue.iter() {
-                    let avail = order.volume.min(formatted_volume - total_volume);
-                    if avail == 0 {
-                        break;
-                    }
-
-                    total_volume += avail;
-                    weighted_price_sum += (price as u128) * (avail as u128);
-
-                    match order.exchange.as_str() {
-                        "Alpaca" => alpaca_volume += avail,
-                        "Kraken" => kraken_volume += avail,
-                        "Bybit" => bybit_volume += avail,
-                        _ => {}
-                    }
-
-                    if total_volume >= formatted_volume {
-                        break;
-                    }
-                }
-                if total_volume >= formatted_volume {
-                    break;
+        'levels: for (&price, level) in self.levels() {
+            for (venue, volume) in level.iter() {
+                // Volume still wanted, in base units at this price.
+                let wanted = match order.unit {
+                    VolumeUnit::Base => target.saturating_sub(total_volume as u128),
+                    VolumeUnit::Quote => target.saturating_sub(weighted_price_sum) / price.max(1) as u128,
+                };
+                if wanted == 0 {
+                    fully_filled = true;
+                    break 'levels;
                 }
-            }
-        } else {
-            for (&price, queue) in self.orders.iter() {
-                for order in queue.iter() {
-                    let avail = order.volume.min(formatted_volume - total_volume);
-                    if avail == 0 {
-                        break;
-                    }
+                let avail = volume.min(wanted.min(u64::MAX as u128) as u64);
 
-                    total_volume += avail;
-                    weighted_price_sum += (price as u128) * (avail as u128);
-
-                    match order.exchange.as_str() {
-                        "Alpaca" => alpaca_volume += avail,
-                        "Kraken" => kraken_volume += avail,
-                        "Bybit" => bybit_volume += avail,
-                        _ => {}
-                    }
+                total_volume += avail;
+                weighted_price_sum += (price as u128) * (avail as u128);
+                worst_price = price;
 
-                    if total_volume >= formatted_volume {
-                        break;
-                    }
+                let exchange = &venues[venue as usize];
+                venue_volumes[venue as usize] += avail;
+                if let (Some(rate), Some(quote)) = (level.rate(venue), venue_quotes.get(exchange)) {
+                    let (weighted, taken) = converted.entry(quote.as_str()).or_default();
+                    *weighted += rate * avail as f64;
+                    *taken += avail;
                 }
-                if total_volume >= formatted_volume {
-                    break;
+                if order.detailed {
+                    slices.push(FillSlice {
+                        exchange: exchange.clone(),
+                        price: price as f64 / 100.0,
+                        volume: avail as f64 / 1_000_000.0,
+                    });
                 }
             }
         }
-
-        self.pause.store(false, Ordering::SeqCst);
+        // The last slice may have used up the target with nothing left to walk.
+        fully_filled |= match order.unit {
+            VolumeUnit::Base => total_volume as u128 >= target,
+            VolumeUnit::Quote => target.saturating_sub(weighted_price_sum) < worst_price.max(1) as u128,
+        };
 
         if total_volume == 0 {
             return Err(OrderBookError::InsufficientVolume("Not enough volume available".to_string()));
@@ -165,19 +533,40 @@ impl SideOrderBook {
 
         let vwap_scaled = (weighted_price_sum / total_volume as u128) as f64 / 100.0;
 
+        let elapsed = started.elapsed();
+        metrics().quote_duration.observe(elapsed.as_secs_f64());
+        debug!(
+            latency_us = elapsed.as_micros() as u64,
+            filled = total_volume as f64 / 1_000_000.0,
+            vwap = vwap_scaled,
+            "Quote computed"
+        );
+
         let total_volume_scaled = total_volume as f64 / 1_000_000.0;
-        let alpaca_volume_scaled = alpaca_volume as f64 / 1_000_000.0;
-        let kraken_volume_scaled = kraken_volume as f64 / 1_000_000.0;
-        let bybit_volume_scaled = bybit_volume as f64 / 1_000_000.0;
+        let venue_volumes_scaled = venues
+            .iter()
+            .zip(venue_volumes)
+            .filter(|&(_, volume)| volume > 0)
+            .map(|(exchange, volume)| (exchange.clone(), volume as f64 / 1_000_000.0))
+            .collect();
 
         Ok(PriceResponse {
             symbol,
-            side: if self.is_buy { OrderSide::Buy } else { OrderSide::Sell },
+            side,
             total_volume: total_volume_scaled,
-            alpaca_volume: alpaca_volume_scaled,
-            kraken_volume: kraken_volume_scaled,
-            bybit_volume: bybit_volume_scaled,
+            venue_volumes: venue_volumes_scaled,
             vwap: vwap_scaled,
+            conversion_rates: converted
+                .into_iter()
+                .map(|(quote, (weighted, taken))| (quote.to_string(), weighted / taken as f64))
+                .collect(),
+            fully_filled,
+            detail: order.detailed.then(|| QuoteDetail {
+                slices,
+                worst_price: worst_price as f64 / 100.0,
+                mid_price: None,
+                slippage_bps: None,
+            }),
         })
     }
 
@@ -187,42 +576,52 @@ impl SideOrderBook {
 
 
 pub struct UnifiedOrderBook {
-    main_receiver: tokio::sync::Mutex<UnboundedReceiver<OBOrder>>,
-    buy_sender: UnboundedSender<OBOrder>,
-    sell_sender: UnboundedSender<OBOrder>,
-    buy_query_sender: UnboundedSender<OrderRequest>,
-    sell_query_sender: UnboundedSender<OrderRequest>,
+    main_receiver: tokio::sync::Mutex<BookReceiver>,
+    writer_sender: Sender<OBOrder>,
+    command_sender: Sender<BookCommand>,
+    snapshots: watch::Receiver<Arc<BookState>>,
+    /// Updates handed to the writer so far, for `settle`.
+    dispatched: AtomicU64,
+    updates: broadcast::Sender<LevelUpdate>,
     active: Arc<AtomicBool>,
+    /// Quote currency per venue, for naming the rates converted levels carry.
+    venue_quotes: HashMap<String, String>,
 }
 
 impl UnifiedOrderBook {
-    pub fn new(main_receiver: UnboundedReceiver<OBOrder>) -> Self {
-        let (buy_sender, buy_receiver) = unbounded_channel();
-        let (sell_sender, sell_receiver) = unbounded_channel();
+    /// Creates a book fed by `main_receiver` that keeps at most `max_depth`
+    /// levels per side from each venue.
+    pub fn new(main_receiver: BookReceiver, max_depth: usize) -> Self {
+        let (writer_sender, writer_receiver) = channel(WRITER_CHANNEL_CAPACITY);
+        let (command_sender, command_receiver) = channel(COMMAND_CHANNEL_CAPACITY);
 
-        let (buy_query_sender, buy_query_receiver) = unbounded_channel();
-        let (sell_query_sender, sell_query_receiver) = unbounded_channel();
+        let (updates, _) = broadcast::channel(UPDATE_BROADCAST_CAPACITY);
 
         let active = Arc::new(AtomicBool::new(true));
 
-        {
-            tokio::spawn(SideOrderBook::new(buy_query_receiver, buy_receiver, true).run());
-        }
-        {
-            tokio::spawn(SideOrderBook::new(sell_query_receiver, sell_receiver, false).run());
-        }
+        let (writer, snapshots) = BookWriter::new(max_depth, command_receiver, writer_receiver, updates.clone());
+        tokio::spawn(writer.run());
 
         Self {
             main_receiver: tokio::sync::Mutex::new(main_receiver),
-            buy_sender,
-            sell_sender,
-            buy_query_sender,
-            sell_query_sender,
+            writer_sender,
+            command_sender,
+            snapshots,
+            dispatched: AtomicU64::new(0),
+            updates,
             active,
-
+            venue_quotes: HashMap::new(),
         }
     }
 
+    /// Names the quote currency each venue streams, so quotes can report the
+    /// rates their converted volume was priced at; set when the feed is
+    /// converted upstream.
+    pub fn with_venue_quotes(mut self, venue_quotes: HashMap<String, String>) -> Self {
+        self.venue_quotes = venue_quotes;
+        self
+    }
+
     pub async fn run(&self) {
         while self.active.load(Ordering::SeqCst) {
             let maybe_order = {
@@ -232,13 +631,8 @@ impl UnifiedOrderBook {
 
             match maybe_order {
                 Some(order) => {
-                    match order.side {
-                        OrderSide::Buy => {
-                            let _ = self.buy_sender.send(order);
-                        }
-                        OrderSide::Sell => {
-                            let _ = self.sell_sender.send(order);
-                        }
+                    if self.writer_sender.send(order).await.is_ok() {
+                        self.dispatched.fetch_add(1, Ordering::SeqCst);
                     }
                 }
                 None => {
@@ -253,10 +647,445 @@ impl UnifiedOrderBook {
         self.active.store(false, Ordering::SeqCst);
     }
 
-    pub async fn get_quote(&self, order: OrderRequest) -> Result<(), OrderBookError> {
-        match order.side {
-            OrderSide::Buy => self.buy_query_sender.send(order).map_err(|_| OrderBookError::ChannelSendError),
-            OrderSide::Sell => self.sell_query_sender.send(order).map_err(|_| OrderBookError::ChannelSendError),
+    /// Waits until the writer has published every update dispatched so far.
+    /// Reads are served from the last snapshot, so use this before reading
+    /// when the result must reflect a finished feed, e.g. after a replay.
+    pub async fn settle(&self) {
+        let dispatched = self.dispatched.load(Ordering::SeqCst);
+        let mut snapshots = self.snapshots.clone();
+        let _ = snapshots.wait_for(|state| state.applied >= dispatched || !state.active).await;
+    }
+
+    /// Waits until the writer has published more than `applied` updates and
+    /// returns how many it has, or `None` once it stopped short of that.
+    pub async fn next_applied(&self, applied: u64) -> Option<u64> {
+        let mut snapshots = self.snapshots.clone();
+        let state = snapshots.wait_for(|state| state.applied > applied || !state.active).await.ok()?;
+        (state.applied > applied).then_some(state.applied)
+    }
+
+    fn state(&self) -> Arc<BookState> {
+        self.snapshots.borrow().clone()
+    }
+
+    /// Prices `order` against the opposite side of the book: buys walk the
+    /// asks and sells walk the bids.
+    #[instrument(name = "quote", skip(self, order), fields(symbol = %order.symbol, side = ?order.side, volume = order.volume))]
+    pub async fn get_quote(&self, order: OrderRequest) -> Result<PriceResponse, OrderBookError> {
+        let state = self.state();
+        if !state.active {
+            return Err(OrderBookError::InactiveOrderBook);
         }
+        let book_side = match order.side {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        };
+        let mut quote = state.side(&book_side).get_best_quote(&state.venues, &self.venue_quotes, order)?;
+        if let Some(detail) = &mut quote.detail {
+            if let (Some(bid), Some(ask)) = (state.bids.best_price(), state.asks.best_price()) {
+                let mid = (bid + ask) as f64 / 200.0;
+                let slippage = match quote.side {
+                    OrderSide::Buy => quote.vwap - mid,
+                    OrderSide::Sell => mid - quote.vwap,
+                };
+                detail.mid_price = Some(mid);
+                detail.slippage_bps = Some(slippage / mid * 10_000.0);
+            }
+        }
+        Ok(quote)
+    }
+
+    /// Up to `depth` aggregated price levels on each side of the book, both
+    /// taken from the same snapshot.
+    pub async fn get_book(&self, depth: usize) -> Result<BookSnapshot, OrderBookError> {
+        let state = self.state();
+        Ok(BookSnapshot {
+            bids: state.bids.get_depth(&state.venues, depth),
+            asks: state.asks.get_depth(&state.venues, depth),
+            venue_sequences: state.venue_sequences(),
+        })
+    }
+
+    /// Full book on both sides, tagged with the sequence number of the last
+    /// update applied to each side. Pair with `subscribe_updates`, subscribing
+    /// first, and skip updates at or below the snapshot's sequence.
+    pub async fn get_sequenced_book(&self) -> Result<SequencedBookSnapshot, OrderBookError> {
+        let state = self.state();
+        Ok(SequencedBookSnapshot {
+            bid_sequence: state.bids.sequence,
+            ask_sequence: state.asks.sequence,
+            bids: state.bids.get_depth(&state.venues, usize::MAX),
+            asks: state.asks.get_depth(&state.venues, usize::MAX),
+            venue_sequences: state.venue_sequences(),
+        })
+    }
+
+    /// Every per-venue level change applied to either side of the book.
+    pub fn subscribe_updates(&self) -> broadcast::Receiver<LevelUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Removes every level `exchange` has in the book, on both sides, and
+    /// returns the venue's new epoch. Updates tagged with an older epoch are
+    /// dropped from then on, so a feed restarted after the clear must tag its
+    /// updates with the returned one.
+    pub async fn clear_venue(&self, exchange: &str) -> Result<u64, OrderBookError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.command_sender
+            .send(BookCommand::ClearVenue(exchange.to_string(), reply_sender))
+            .await
+            .map_err(|_| OrderBookError::ChannelSendError)?;
+        reply_receiver
+            .await
+            .map_err(|e| OrderBookError::ReceiveError(e.to_string()))
+    }
+
+    /// Best price per venue on both sides, taken from the same snapshot.
+    pub async fn get_venue_tops(&self) -> Result<VenueTops, OrderBookError> {
+        let state = self.state();
+        Ok(VenueTops {
+            bids: state.bids.get_venue_tops(&state.venues),
+            asks: state.asks.get_venue_tops(&state.venues),
+            venue_sequences: state.venue_sequences(),
+        })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::channel::{book_channel, OverloadPolicy};
+
+    #[tokio::test]
+    async fn snapshot_pairs_both_sides_with_venue_sequences() {
+        let (sender, receiver) = book_channel("test_book", 64, OverloadPolicy::Block);
+        let order_book = Arc::new(UnifiedOrderBook::new(receiver, 10));
+
+        sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Buy, 1_000_000, 10_000)).await.unwrap();
+        sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Sell, 2_000_000, 10_010)).await.unwrap();
+        sender.send(OBOrder::new("Bybit".to_string(), OrderSide::Sell, 3_000_000, 10_005)).await.unwrap();
+        drop(sender);
+
+        order_book.run().await;
+        order_book.settle().await;
+
+        let book = order_book.get_book(10).await.unwrap();
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.asks[0].price, 100.05);
+        assert_eq!(book.venue_sequences["Kraken"], 2);
+        assert_eq!(book.venue_sequences["Bybit"], 1);
+
+        let tops = order_book.get_venue_tops().await.unwrap();
+        assert_eq!(tops.venue_sequences, book.venue_sequences);
+        assert_eq!(tops.asks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn detailed_quote_lists_slices_and_slippage() {
+        let (sender, receiver) = book_channel("test_detail", 64, OverloadPolicy::Block);
+        let order_book = Arc::new(UnifiedOrderBook::new(receiver, 10));
+
+        sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Buy, 1_000_000, 10_000)).await.unwrap();
+        sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Sell, 1_000_000, 10_010)).await.unwrap();
+        sender.send(OBOrder::new("Bybit".to_string(), OrderSide::Sell, 1_000_000, 10_030)).await.unwrap();
+        drop(sender);
+        order_book.run().await;
+        order_book.settle().await;
+
+        let request = |volume| OrderRequest {
+            symbol: "SOL/USD".to_string(),
+            side: OrderSide::Buy,
+            volume,
+            unit: VolumeUnit::Base,
+            detailed: true,
+        };
+        let quote = order_book.get_quote(request(1.5)).await.unwrap();
+        let detail = quote.detail.unwrap();
+        assert!(quote.fully_filled);
+        assert_eq!(detail.slices, vec![
+            FillSlice { exchange: "Kraken".to_string(), price: 100.10, volume: 1.0 },
+            FillSlice { exchange: "Bybit".to_string(), price: 100.30, volume: 0.5 },
+        ]);
+        assert_eq!(detail.worst_price, 100.30);
+        assert_eq!(detail.mid_price, Some(100.05));
+        // VWAP 100.16 is 0.11 above a 100.05 mid.
+        assert!((detail.slippage_bps.unwrap() - 0.11 / 100.05 * 10_000.0).abs() < 1e-6);
+
+        let partial = order_book.get_quote(request(3.0)).await.unwrap();
+        assert!(!partial.fully_filled);
+        assert_eq!(partial.total_volume, 2.0);
+
+        // 100.10 buys the first unit and the remaining 50.15 half of the next.
+        let notional = order_book.get_quote(OrderRequest { unit: VolumeUnit::Quote, ..request(150.25) }).await.unwrap();
+        assert!(notional.fully_filled);
+        assert_eq!(notional.venue_volumes, BTreeMap::from([("Bybit".to_string(), 0.5), ("Kraken".to_string(), 1.0)]));
+    }
+
+    #[tokio::test]
+    async fn quote_reports_the_rates_its_volume_was_converted_at() {
+        let (sender, receiver) = book_channel("test_rates", 64, OverloadPolicy::Block);
+        let venue_quotes = HashMap::from([("Bybit".to_string(), "USDT".to_string())]);
+        let order_book = Arc::new(UnifiedOrderBook::new(receiver, 10).with_venue_quotes(venue_quotes));
+
+        let bybit = |price, rate| OBOrder { rate: Some(rate), ..OBOrder::new("Bybit".to_string(), OrderSide::Sell, 1_000_000, price) };
+        sender.send(bybit(10_000, 0.999)).await.unwrap();
+        sender.send(bybit(10_010, 1.002)).await.unwrap();
+        sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Sell, 1_000_000, 10_005)).await.unwrap();
+        drop(sender);
+        order_book.run().await;
+        order_book.settle().await;
+
+        let request = |volume| OrderRequest {
+            symbol: "SOL/USD".to_string(),
+            side: OrderSide::Buy,
+            volume,
+            unit: VolumeUnit::Base,
+            detailed: false,
+        };
+        let quote = order_book.get_quote(request(2.0)).await.unwrap();
+        assert_eq!(quote.conversion_rates.keys().collect::<Vec<_>>(), vec!["USDT"]);
+        assert!((quote.conversion_rates["USDT"] - 0.999).abs() < 1e-12);
+
+        // Half a unit more from the second Bybit level, converted at 1.002.
+        let quote = order_book.get_quote(request(2.5)).await.unwrap();
+        assert!((quote.conversion_rates["USDT"] - (0.999 + 0.5 * 1.002) / 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn level_keeps_running_total_and_fill_order() {
+        let mut level = Level::default();
+        assert!(level.set(2, 5, None));
+        assert!(level.set(0, 3, None));
+        assert!(!level.set(0, 3, None));
+        assert!(level.set(2, 1, None));
+        assert_eq!(level.total_volume, 4);
+        assert_eq!(level.iter().collect::<Vec<_>>(), vec![(2, 1), (0, 3)]);
+
+        assert!(level.set(2, 0, None));
+        assert!(!level.set(1, 0, None));
+        assert_eq!(level.total_volume, 3);
+        assert_eq!(level.iter().collect::<Vec<_>>(), vec![(0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn prunes_levels_beyond_venue_depth() {
+        let (sender, receiver) = book_channel("test_prune", 64, OverloadPolicy::Block);
+        let order_book = Arc::new(UnifiedOrderBook::new(receiver, 2));
+
+        for price in [10_000, 10_002, 10_001] {
+            sender.send(OBOrder::new("Kraken".to_string(), OrderSide::Buy, 1_000_000, price)).await.unwrap();
+        }
+        sender.send(OBOrder::new("Bybit".to_string(), OrderSide::Buy, 1_000_000, 9_990)).await.unwrap();
+        drop(sender);
+
+        order_book.run().await;
+        order_book.settle().await;
+
+        let prices: Vec<f64> = order_book.get_book(10).await.unwrap().bids.iter().map(|level| level.price).collect();
+        assert_eq!(prices, vec![100.02, 100.01, 99.90]);
+    }
+
+    #[test]
+    fn clear_drops_updates_queued_before_it() {
+        let mut writer = writer(10);
+        let order = |price, epoch| OBOrder { epoch, ..OBOrder::new("Kraken".to_string(), OrderSide::Buy, 1_000_000, price) };
+        writer.process_order(order(10_000, 0));
+
+        assert_eq!(writer.clear_venue("Kraken"), 1);
+        // Still queued from the old connection when the clear ran.
+        writer.process_order(order(10_001, 0));
+        writer.process_order(order(10_002, 1));
+
+        let prices: Vec<u64> = writer.state.bids.orders.keys().copied().collect();
+        assert_eq!(prices, vec![10_002]);
+    }
+
+    /// Venues the property tests spread updates over, one of them without an
+    /// adapter, since quotes must account for any venue name.
+    const PROPERTY_VENUES: [&str; 4] = ["Kraken", "Bybit", "OKX", "Gemini"];
+
+    fn updates() -> impl Strategy<Value = Vec<OBOrder>> {
+        let update = (0..PROPERTY_VENUES.len(), any::<bool>(), 9_990..10_010u64, prop_oneof![1 => Just(0u64), 3 => 1..5_000_000u64]);
+        prop::collection::vec(update, 0..400).prop_map(|updates| {
+            updates
+                .into_iter()
+                .map(|(venue, is_buy, price, volume)| {
+                    let side = if is_buy { OrderSide::Buy } else { OrderSide::Sell };
+                    OBOrder::new(PROPERTY_VENUES[venue].to_string(), side, volume, price)
+                })
+                .collect()
+        })
+    }
+
+    /// A writer that is driven directly rather than from its channels.
+    fn writer(max_depth: usize) -> BookWriter {
+        let (_, command_receiver) = channel(1);
+        let (_, receiver) = channel(1);
+        let (updates, _) = broadcast::channel(1);
+        BookWriter::new(max_depth, command_receiver, receiver, updates).0
+    }
+
+    /// Volume per venue at each `(is_buy, price)`, kept the naive way.
+    fn reference(updates: &[OBOrder]) -> BTreeMap<(bool, u64), BTreeMap<String, u64>> {
+        let mut book: BTreeMap<(bool, u64), BTreeMap<String, u64>> = BTreeMap::new();
+        for update in updates {
+            let key = (matches!(update.side, OrderSide::Buy), update.price);
+            let level = book.entry(key).or_default();
+            if update.volume == 0 {
+                level.remove(&update.exchange);
+            } else {
+                level.insert(update.exchange.clone(), update.volume);
+            }
+            if level.is_empty() {
+                book.remove(&key);
+            }
+        }
+        book
+    }
+
+    proptest! {
+        #[test]
+        fn book_matches_reference_model(updates in updates()) {
+            let mut writer = writer(usize::MAX);
+            for update in updates.clone() {
+                writer.process_order(update);
+            }
+            let state = &writer.state;
+
+            let mut book = BTreeMap::new();
+            for (is_buy, side) in [(true, &state.bids), (false, &state.asks)] {
+                for (&price, level) in &side.orders {
+                    prop_assert!(!level.is_empty(), "empty level left at {}", price);
+                    prop_assert_eq!(level.total_volume, level.iter().map(|(_, volume)| volume).sum::<u64>());
+                    let volumes: BTreeMap<String, u64> = level
+                        .iter()
+                        .map(|(venue, volume)| (state.venues[venue as usize].clone(), volume))
+                        .collect();
+                    prop_assert!(volumes.values().all(|&volume| volume > 0));
+                    prop_assert_eq!(volumes.len(), level.queue.len());
+                    book.insert((is_buy, price), volumes);
+                }
+            }
+            prop_assert_eq!(book, reference(&updates));
+        }
+
+        #[test]
+        fn double_buffered_batches_match_one_pass(
+            updates in updates(),
+            batch in 1..50usize,
+            held in prop::collection::vec(any::<bool>(), 0..40),
+        ) {
+            let mut one_pass = writer(usize::MAX);
+            for update in updates.clone() {
+                one_pass.process_order(update);
+            }
+
+            let mut batched = writer(usize::MAX);
+            let mut readers = Vec::new();
+            for (index, chunk) in updates.chunks(batch).enumerate() {
+                batched.begin_batch();
+                for update in chunk {
+                    batched.process_order(update.clone());
+                }
+                batched.publish_snapshot();
+                // A reader keeping a snapshot makes the writer clone instead of swapping.
+                if held.get(index).copied().unwrap_or(false) {
+                    readers.push(batched.state.clone());
+                }
+            }
+
+            let sides = [(&batched.state.bids, &one_pass.state.bids), (&batched.state.asks, &one_pass.state.asks)];
+            for (batched_side, one_pass_side) in sides {
+                prop_assert_eq!(batched_side.sequence, one_pass_side.sequence);
+                prop_assert!(batched_side.orders.keys().eq(one_pass_side.orders.keys()));
+                for (batched_level, one_pass_level) in batched_side.orders.values().zip(one_pass_side.orders.values()) {
+                    prop_assert!(batched_level.iter().eq(one_pass_level.iter()));
+                }
+            }
+            prop_assert_eq!(&batched.state.venue_sequences, &one_pass.state.venue_sequences);
+        }
+
+        #[test]
+        fn venue_depth_stays_within_cap(updates in updates(), max_depth in 1..6usize) {
+            let mut writer = writer(max_depth);
+            for update in updates {
+                writer.process_order(update);
+            }
+            for side in [&writer.state.bids, &writer.state.asks] {
+                for venue in 0..writer.state.venues.len() as VenueId {
+                    let depth = side.orders.values().filter(|level| level.iter().any(|(queued, _)| queued == venue)).count();
+                    prop_assert!(depth <= max_depth);
+                }
+            }
+        }
+
+        #[test]
+        fn quotes_never_overfill_and_price_within_touched_levels(
+            updates in updates(),
+            is_buy in any::<bool>(),
+            volume in 0.0..40.0f64,
+            notional in any::<bool>(),
+        ) {
+            let mut writer = writer(usize::MAX);
+            for update in updates {
+                writer.process_order(update);
+            }
+            let state = &writer.state;
+            let side = if is_buy { &state.bids } else { &state.asks };
+            let request = OrderRequest {
+                symbol: "SOL/USD".to_string(),
+                side: if is_buy { OrderSide::Sell } else { OrderSide::Buy },
+                // A notional of the same figure in dollars buys a fraction of a unit.
+                volume: if notional { volume * 100.0 } else { volume },
+                unit: if notional { VolumeUnit::Quote } else { VolumeUnit::Base },
+                detailed: true,
+            };
+            let Ok(quote) = side.get_best_quote(&state.venues, &HashMap::new(), request) else {
+                return Ok(());
+            };
+
+            let filled = (quote.total_volume * 1_000_000.0).round() as u64;
+            prop_assert!(filled > 0);
+            let by_venue: f64 = quote.venue_volumes.values().sum();
+            prop_assert!((by_venue - quote.total_volume).abs() < 1e-6);
+
+            let detail = quote.detail.as_ref().unwrap();
+            let sliced: f64 = detail.slices.iter().map(|slice| slice.volume).sum();
+            prop_assert!((sliced - quote.total_volume).abs() < 1e-6);
+            prop_assert_eq!(detail.slices.last().map(|slice| slice.price), Some(detail.worst_price));
+            let available: u64 = side.orders.values().map(|level| level.total_volume).sum();
+            if notional {
+                let target = (volume * 100.0 * 100_000_000.0) as u128;
+                let spent: u128 = detail
+                    .slices
+                    .iter()
+                    .map(|slice| (slice.price * 100.0).round() as u128 * (slice.volume * 1_000_000.0).round() as u128)
+                    .sum();
+                prop_assert!(spent <= target);
+                prop_assert!(quote.fully_filled || filled == available);
+            } else {
+                let requested = (volume * 1_000_000.0) as u64;
+                prop_assert!(filled <= requested);
+                prop_assert_eq!(quote.fully_filled, filled == requested);
+                prop_assert_eq!(quote.fully_filled, available >= requested);
+            }
+
+            // The levels a fill of that size has to reach, best first.
+            let mut reached = 0;
+            let touched: Vec<u64> = side
+                .levels()
+                .take_while(|(_, level)| {
+                    let more = reached < filled;
+                    reached += level.total_volume;
+                    more
+                })
+                .map(|(&price, _)| price)
+                .collect();
+            let (low, high) = (*touched.iter().min().unwrap(), *touched.iter().max().unwrap());
+            let vwap = (quote.vwap * 100.0).round() as u64;
+            prop_assert!(low <= vwap && vwap <= high, "vwap {} outside {}..={}", vwap, low, high);
+        }
+    }
+}
+
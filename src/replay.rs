@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tracing::error;
+use crate::channel::{book_channel, BookReceiver, BookSender, OverloadPolicy};
+use crate::types::OBOrder;
+
+/// One line of a recording: a book update and when it arrived, in
+/// milliseconds since the recording started.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecordedOrder {
+    pub offset_ms: u64,
+    pub order: OBOrder,
+}
+
+/// Tees every update from `receiver` into a JSON-lines file at `path` and
+/// returns a receiver that yields the same updates for the order book.
+pub async fn spawn_recorder(path: PathBuf, capacity: usize, mut receiver: BookReceiver) -> BookReceiver {
+    let (forward_sender, forward_receiver) = book_channel("recorded", capacity, OverloadPolicy::Block);
+
+    let file = match File::create(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!(path = %path.display(), error = %e, error_kind = "io", "Failed to create recording");
+            return receiver;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut writer = BufWriter::new(file);
+        let started = Instant::now();
+
+        while let Some(order) = receiver.recv().await {
+            let record = RecordedOrder {
+                offset_ms: started.elapsed().as_millis() as u64,
+                order,
+            };
+            match serde_json::to_string(&record) {
+                Ok(line) => {
+                    if let Err(e) = writer.write_all(format!("{}\n", line).as_bytes()).await {
+                        error!(error = %e, error_kind = "io", "Failed to write recording");
+                    }
+                }
+                Err(e) => error!(error = %e, error_kind = "serialize", "Failed to serialize recorded order"),
+            }
+            if forward_sender.send(record.order).await.is_err() {
+                break;
+            }
+        }
+
+        writer.flush().await.ok();
+    });
+
+    forward_receiver
+}
+
+/// Feeds a recording into `sender`. A `speed` of 1.0 reproduces the original
+/// timing, larger values replay faster and 0.0 replays without any delay.
+/// Returns the number of updates replayed.
+pub async fn replay_file(path: PathBuf, speed: f64, sender: BookSender) -> std::io::Result<usize> {
+    let file = File::open(&path).await?;
+    let mut lines = BufReader::new(file).lines();
+    let started = Instant::now();
+    let mut replayed = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: RecordedOrder = serde_json::from_str(&line)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        if speed > 0.0 {
+            let due = Duration::from_secs_f64(record.offset_ms as f64 / 1000.0 / speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        if sender.send(record.order).await.is_err() {
+            break;
+        }
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
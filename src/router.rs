@@ -0,0 +1,102 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, info_span, instrument, Instrument};
+use serde::{Deserialize, Serialize};
+use crate::{
+    errors::OrderBookError,
+    session::Session,
+    types::{Fill, Order, OrderRequest, PriceResponse},
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoutedOrder {
+    pub exchange: String,
+    pub volume: f64,
+    pub estimated_fee: f64,
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OrderReport {
+    pub dry_run: bool,
+    pub quote: PriceResponse,
+    pub routes: Vec<RoutedOrder>,
+}
+
+/// Quotes `request` against the unified book and splits it into one market
+/// order per venue, sized by that venue's share of the quote and rounded down
+/// to the venue's lot size. Child orders below a venue's minimums are skipped.
+/// With `dry_run` set the plan is returned without sending anything.
+#[instrument(skip(session, request), fields(symbol = %request.symbol, side = ?request.side, volume = request.volume))]
+pub async fn route_order(session: &Session, request: OrderRequest, dry_run: bool) -> Result<OrderReport, OrderBookError> {
+    let symbol = request.symbol.clone();
+    let side = request.side.clone();
+    let quote = session.order_book.get_quote(request).await?;
+
+    let mut routes = Vec::new();
+    for (name, &volume) in &quote.venue_volumes {
+        let name = name.as_str();
+
+        let rules = session.instruments.get(name);
+        let volume = rules.as_ref().map_or(volume, |info| info.round_qty(volume));
+        let rejection = rules.as_ref().and_then(|info| info.check(volume, Some(quote.vwap)).err());
+
+        let exchange = session.exchange(name);
+        let estimated_fee = exchange.map_or(0.0, |exchange| volume * quote.vwap * exchange.taker_fee());
+
+        let status = if let Some(reason) = rejection {
+            format!("skipped: {}", reason)
+        } else if dry_run {
+            "dry-run".to_string()
+        } else {
+            match exchange.zip(session.venue_instrument(name)) {
+                Some((exchange, instrument)) => {
+                    let child = Order {
+                        instrument: instrument.clone(),
+                        side: side.clone(),
+                        volume,
+                    };
+                    let started = Instant::now();
+                    let result = exchange
+                        .place_order(child)
+                        .instrument(info_span!("child_order", venue = name, volume))
+                        .await;
+                    let latency_ms = started.elapsed().as_millis() as u64;
+
+                    match result {
+                        Ok(()) => {
+                            info!(venue = name, volume, latency_ms, "Child order placed");
+                            let timestamp_ms = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .expect("Time went backwards")
+                                .as_millis() as u64;
+                            // No subscribers is not an error.
+                            let _ = session.fills.send(Fill {
+                                exchange: name.to_string(),
+                                symbol: symbol.clone(),
+                                side: side.clone(),
+                                volume,
+                                price: quote.vwap,
+                                timestamp_ms,
+                            });
+                            "placed".to_string()
+                        }
+                        Err(e) => {
+                            error!(venue = name, volume, latency_ms, error = %e, error_kind = "order", "Child order failed");
+                            format!("failed: {}", e)
+                        }
+                    }
+                }
+                None => "failed: venue not connected".to_string(),
+            }
+        };
+
+        routes.push(RoutedOrder {
+            exchange: name.to_string(),
+            volume,
+            estimated_fee,
+            status,
+        });
+    }
+
+    Ok(OrderReport { dry_run, quote, routes })
+}
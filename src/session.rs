@@ -0,0 +1,335 @@
+use std::{collections::HashMap, env, path::PathBuf, sync::Arc};
+use tracing::{error, info, warn};
+use tokio::sync::{
+    broadcast,
+    mpsc::{unbounded_channel, UnboundedSender},
+    Notify,
+};
+use crate::{
+    channel::{book_channel, BookSender, OverloadPolicy},
+    config,
+    conversion,
+    errors::ExchangeError,
+    exchanges::{alpaca, binance, bybit, coinbase, exchange::{Environment, Exchange}, kraken, okx},
+    instruments::InstrumentRegistry,
+    order_book::UnifiedOrderBook,
+    replay,
+    metrics::metrics,
+    types::{Fill, Instrument, OrderSide, Trade},
+};
+
+const FILL_BROADCAST_CAPACITY: usize = 256;
+const TRADE_BROADCAST_CAPACITY: usize = 4096;
+
+pub type SharedExchange = Arc<dyn Exchange + Send + Sync>;
+
+/// How a session connects to its venues.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub environment: Environment,
+    /// Quote currencies treated as interchangeable with the instrument's own
+    /// quote, e.g. `USDT` to merge SOL/USDT venues into a SOL/USD book.
+    pub bridged_quotes: Vec<String>,
+    /// Also append every book update to this file.
+    pub record: Option<PathBuf>,
+    /// Also stream public trades from venues that support it.
+    pub trades: bool,
+    /// Book updates buffered between the venues and the book.
+    pub channel_capacity: usize,
+    /// What the feed channel does once `channel_capacity` updates are waiting.
+    pub overload_policy: OverloadPolicy,
+    /// Levels per side the book keeps from each venue.
+    pub max_depth: usize,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            environment: Environment::default(),
+            bridged_quotes: Vec::new(),
+            record: None,
+            trades: false,
+            channel_capacity: config::BOOK_CHANNEL_CAPACITY,
+            overload_policy: OverloadPolicy::default(),
+            max_depth: config::ORDER_BOOK_DEPTH,
+        }
+    }
+}
+
+/// A running unified order book fed by live subscriptions on every venue
+/// that lists the instrument in its quote currency or a bridged one.
+pub struct Session {
+    pub instrument: Instrument,
+    pub order_book: Arc<UnifiedOrderBook>,
+    pub exchanges: Vec<SharedExchange>,
+    pub environment: Environment,
+    /// Tick, lot and minimum sizes per venue, loaded in the background.
+    pub instruments: Arc<InstrumentRegistry>,
+    /// Child orders accepted by a venue while routing.
+    pub fills: broadcast::Sender<Fill>,
+    /// Public trades, when the session was started with `trades`.
+    pub trades: broadcast::Sender<Trade>,
+    /// The pair each connected venue actually trades, keyed by venue name.
+    venue_instruments: HashMap<String, Instrument>,
+    shutdown_notify: Arc<Notify>,
+}
+
+impl Session {
+    /// Connects every venue that lists `instrument` and starts the unified order book.
+    pub async fn start(instrument: &Instrument, options: SessionOptions) -> Self {
+        let SessionOptions { environment, bridged_quotes, record, trades: stream_trades, channel_capacity, overload_policy, max_depth } = options;
+        let (sender, receiver) = book_channel("main", channel_capacity, overload_policy);
+        let resync_sender = sender.clone();
+
+        let instruments = Arc::new(InstrumentRegistry::default());
+        let mut exchanges = Vec::new();
+        let mut venue_instruments = HashMap::new();
+        for exchange in Self::exchanges_from_env(environment, instruments.clone(), sender) {
+            match Self::listed_instrument(exchange.as_ref(), instrument, &bridged_quotes) {
+                Some(listed) => {
+                    if listed != *instrument {
+                        info!(venue = exchange.name(), listed = %listed, "Bridging venue into the book");
+                    }
+                    venue_instruments.insert(exchange.name().to_string(), listed);
+                    exchanges.push(exchange);
+                }
+                None => info!(venue = exchange.name(), symbol = %instrument, "Venue does not list symbol in a merged quote currency, skipping"),
+            }
+        }
+
+        // Bridged venues are repriced before recording, so a replay sees the
+        // same book the session did.
+        let venue_quotes: HashMap<String, String> = venue_instruments
+            .iter()
+            .map(|(name, listed)| (name.clone(), listed.quote.clone()))
+            .collect();
+        let receiver = if venue_quotes.values().any(|quote| *quote != instrument.quote) {
+            conversion::spawn_converter(&instrument.quote, venue_quotes.clone(), channel_capacity, receiver)
+        } else {
+            receiver
+        };
+
+        let receiver = match record {
+            Some(path) => replay::spawn_recorder(path, channel_capacity, receiver).await,
+            None => receiver,
+        };
+
+        let order_book = Arc::new(UnifiedOrderBook::new(receiver, max_depth).with_venue_quotes(venue_quotes));
+        let order_book_clone = order_book.clone();
+
+        tokio::spawn(async move {
+            order_book_clone.run().await;
+        });
+
+        info!(symbol = %instrument, ?environment, "Unified order book has been started");
+
+        let shutdown_notify = Arc::new(Notify::new());
+
+        let (trades, _) = broadcast::channel(TRADE_BROADCAST_CAPACITY);
+        let trade_sender = stream_trades.then(|| Self::spawn_trade_forwarder(trades.clone()));
+
+        for exchange in &exchanges {
+            let exchange = exchange.clone();
+            let shutdown_notify = shutdown_notify.clone();
+            let listed = venue_instruments[exchange.name()].clone();
+            let instruments = instruments.clone();
+            let trade_sender = trade_sender.clone();
+            tokio::spawn(async move {
+                let name = exchange.name().to_string();
+                match exchange.fetch_instrument(&listed).await {
+                    Ok(info) => {
+                        info!(venue = %name, symbol = %listed, tick_size = info.tick_size, lot_size = info.lot_size, "Loaded instrument info");
+                        instruments.insert(info);
+                    }
+                    Err(e) => warn!(venue = %name, symbol = %listed, error = %e, error_kind = "instrument", "Failed to load instrument info"),
+                }
+
+                if let Err(e) = exchange.subscribe_ob(&listed, 0).await {
+                    error!(venue = %name, symbol = %listed, error = %e, error_kind = "subscribe", "Failed to subscribe");
+                    return;
+                }
+                info!(venue = %name, symbol = %listed, "Subscribed to order book");
+
+                let mut streaming_trades = false;
+                if let Some(trade_sender) = trade_sender {
+                    match exchange.subscribe_trades(&listed, trade_sender).await {
+                        Ok(()) => {
+                            info!(venue = %name, symbol = %listed, "Subscribed to trades");
+                            streaming_trades = true;
+                        }
+                        Err(ExchangeError::Unsupported(_)) => info!(venue = %name, "Venue has no trade stream, skipping"),
+                        Err(e) => warn!(venue = %name, symbol = %listed, error = %e, error_kind = "subscribe", "Failed to subscribe to trades"),
+                    }
+                }
+
+                shutdown_notify.notified().await;
+
+                if streaming_trades {
+                    if let Err(e) = exchange.unsubscribe_trades(&listed).await {
+                        warn!(venue = %name, symbol = %listed, error = %e, error_kind = "unsubscribe", "Failed to unsubscribe from trades");
+                    }
+                }
+
+                if let Err(e) = exchange.unsubscribe_ob(&listed).await {
+                    warn!(venue = %name, symbol = %listed, error = %e, error_kind = "unsubscribe", "Failed to unsubscribe");
+                } else {
+                    info!(venue = %name, symbol = %listed, "Unsubscribed from order book");
+                }
+            });
+        }
+
+        if overload_policy == OverloadPolicy::DropOldest {
+            Self::spawn_resyncer(resync_sender, order_book.clone(), exchanges.clone(), venue_instruments.clone());
+        }
+
+        let (fills, _) = broadcast::channel(FILL_BROADCAST_CAPACITY);
+
+        Self {
+            instrument: instrument.clone(),
+            order_book,
+            exchanges,
+            environment,
+            instruments,
+            fills,
+            trades,
+            venue_instruments,
+            shutdown_notify,
+        }
+    }
+
+    /// Rebuilds a venue's levels after the feed channel dropped some of its
+    /// updates: clears what the book holds for it and resubscribes.
+    fn spawn_resyncer(
+        sender: BookSender,
+        order_book: Arc<UnifiedOrderBook>,
+        exchanges: Vec<SharedExchange>,
+        venue_instruments: HashMap<String, Instrument>,
+    ) {
+        tokio::spawn(async move {
+            loop {
+                for name in sender.resync_requests().await {
+                    let (Some(exchange), Some(listed)) = (
+                        exchanges.iter().find(|exchange| exchange.name() == name),
+                        venue_instruments.get(&name),
+                    ) else {
+                        continue;
+                    };
+                    warn!(venue = %name, error_kind = "overload", "Dropped book updates, resyncing venue");
+                    let epoch = match order_book.clear_venue(&name).await {
+                        Ok(epoch) => epoch,
+                        Err(e) => {
+                            error!(venue = %name, error = %e, error_kind = "order_book", "Failed to clear venue levels");
+                            continue;
+                        }
+                    };
+                    if let Err(e) = exchange.resync_ob(listed, epoch).await {
+                        error!(venue = %name, symbol = %listed, error = %e, error_kind = "subscribe", "Failed to resync venue");
+                    }
+                }
+            }
+        });
+    }
+
+    /// Collects every venue's trades, counts their volume and republishes them.
+    fn spawn_trade_forwarder(trades: broadcast::Sender<Trade>) -> UnboundedSender<Trade> {
+        let (sender, mut receiver) = unbounded_channel::<Trade>();
+        tokio::spawn(async move {
+            let metrics = metrics();
+            while let Some(trade) = receiver.recv().await {
+                let side = match trade.side {
+                    OrderSide::Buy => "buy",
+                    OrderSide::Sell => "sell",
+                };
+                metrics.trade_volume.with_label_values(&[trade.exchange.as_str(), side]).inc_by(trade.size);
+                // No subscribers is fine; trades are only kept for whoever is listening.
+                let _ = trades.send(trade);
+            }
+        });
+        sender
+    }
+
+    /// The pair `exchange` should stream for `instrument`: the instrument
+    /// itself if listed, otherwise the first bridged quote the venue trades.
+    fn listed_instrument(exchange: &(dyn Exchange + Send + Sync), instrument: &Instrument, bridged_quotes: &[String]) -> Option<Instrument> {
+        std::iter::once(instrument.clone())
+            .chain(bridged_quotes.iter().map(|quote| instrument.with_quote(quote)))
+            .find(|candidate| exchange.venue_symbol(candidate).is_some())
+    }
+
+    /// Builds the adapters for `environment` with their credentials:
+    /// `{VENUE}_API_KEY` in production and `{VENUE}_SANDBOX_API_KEY` in the
+    /// sandbox. The sandbox only runs venues with a testnet market data feed,
+    /// so its book never mixes test and production liquidity.
+    fn exchanges_from_env(
+        environment: Environment,
+        instruments: Arc<InstrumentRegistry>,
+        sender: BookSender,
+    ) -> Vec<SharedExchange> {
+        let optional = |venue: &str, key: &str| env::var(credential_var(environment, venue, key)).unwrap_or_default();
+        let required = |venue: &str, key: &str| {
+            let name = credential_var(environment, venue, key);
+            env::var(&name).unwrap_or_else(|_| panic!("Missing {}", name))
+        };
+
+        let mut exchanges: Vec<SharedExchange> = Vec::new();
+        let sandbox = environment == Environment::Sandbox;
+        if sandbox {
+            info!("Sandbox skips Kraken, Alpaca and Coinbase, which have no test market data feed");
+        } else {
+            let kraken_api_key = required("KRAKEN", "API_KEY");
+            let kraken_api_secret = required("KRAKEN", "API_SECRET");
+            exchanges.push(Arc::new(kraken::KrakenExchange::new(kraken_api_key, kraken_api_secret, instruments.clone(), sender.clone())));
+        }
+
+        let bybit_api_key = required("BYBIT", "API_KEY");
+        let bybit_api_secret = required("BYBIT", "API_SECRET");
+        exchanges.push(Arc::new(bybit::BybitExchange::new(bybit_api_key, bybit_api_secret, environment, instruments.clone(), sender.clone())));
+
+        // Coinbase, Binance and OKX book feeds are public, so their credentials are only needed for orders.
+        if !sandbox {
+            let alpaca_api_key = required("ALPACA", "API_KEY");
+            let alpaca_api_secret = required("ALPACA", "API_SECRET");
+            exchanges.push(Arc::new(alpaca::AlpacaExchange::new(alpaca_api_key, alpaca_api_secret, instruments.clone(), sender.clone())));
+
+            let coinbase_api_key = optional("COINBASE", "API_KEY");
+            let coinbase_api_secret = optional("COINBASE", "API_SECRET");
+            exchanges.push(Arc::new(coinbase::CoinbaseExchange::new(coinbase_api_key, coinbase_api_secret, instruments.clone(), sender.clone())));
+        }
+
+        let binance_api_key = optional("BINANCE", "API_KEY");
+        let binance_api_secret = optional("BINANCE", "API_SECRET");
+        exchanges.push(Arc::new(binance::BinanceExchange::new(binance_api_key, binance_api_secret, environment, instruments.clone(), sender.clone())));
+
+        let okx_api_key = optional("OKX", "API_KEY");
+        let okx_api_secret = optional("OKX", "API_SECRET");
+        let okx_api_passphrase = optional("OKX", "API_PASSPHRASE");
+        exchanges.push(Arc::new(okx::OkxExchange::new(okx_api_key, okx_api_secret, okx_api_passphrase, environment, instruments, sender)));
+
+        exchanges
+    }
+
+    pub fn exchange(&self, name: &str) -> Option<&SharedExchange> {
+        self.exchanges.iter().find(|exchange| exchange.name() == name)
+    }
+
+    pub fn venue_instrument(&self, name: &str) -> Option<&Instrument> {
+        self.venue_instruments.get(name)
+    }
+
+    /// Each venue's taker fee, as a fraction of notional.
+    pub fn taker_fees(&self) -> HashMap<String, f64> {
+        self.exchanges.iter().map(|exchange| (exchange.name().to_string(), exchange.taker_fee())).collect()
+    }
+
+    pub async fn shutdown(&self) {
+        self.shutdown_notify.notify_waiters();
+        self.order_book.stop().await;
+    }
+}
+
+fn credential_var(environment: Environment, venue: &str, key: &str) -> String {
+    match environment {
+        Environment::Production => format!("{}_{}", venue, key),
+        Environment::Sandbox => format!("{}_SANDBOX_{}", venue, key),
+    }
+}
@@ -0,0 +1,28 @@
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum LogFormat {
+    /// Single-line human-readable events
+    #[default]
+    Text,
+    /// Multi-line human-readable events with span context
+    Pretty,
+    /// One JSON object per event, for log aggregation
+    Json,
+}
+
+/// Installs the global subscriber writing to stderr, so stdout stays free for
+/// command output. Filtering follows `RUST_LOG` and defaults to `info`.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
@@ -1,8 +1,53 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
+use crate::config;
+
+/// A canonical trading pair such as SOL/USD, independent of how any one
+/// venue spells it.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    pub base: String,
+    pub quote: String,
+}
+
+impl Instrument {
+    pub fn new(base: &str, quote: &str) -> Self {
+        Instrument {
+            base: base.to_ascii_uppercase(),
+            quote: quote.to_ascii_uppercase(),
+        }
+    }
+
+    /// The same base priced in another quote currency.
+    pub fn with_quote(&self, quote: &str) -> Self {
+        Instrument::new(&self.base, quote)
+    }
+}
+
+/// Parses `SOL/USD` or `SOL-USD`; a bare `SOL` is quoted in `config::QUOTE`.
+impl FromStr for Instrument {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (base, quote) = s.split_once(['/', '-']).unwrap_or((s, config::QUOTE));
+        if base.is_empty() || quote.is_empty() || !(base.chars().chain(quote.chars())).all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid symbol: {}", s));
+        }
+        Ok(Instrument::new(base, quote))
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Order {
-    pub symbol: String,
+    pub instrument: Instrument,
     pub side: OrderSide,
     pub volume: f64,
 }
@@ -19,6 +64,14 @@ pub struct OBOrder {
     pub side: OrderSide,
     pub volume: u64,
     pub price: u64,
+    /// The venue's epoch when the feed connection sending this was started.
+    /// The book drops updates from before it last cleared the venue.
+    #[serde(default)]
+    pub epoch: u64,
+    /// Rate `price` was converted into the book's quote currency at, when the
+    /// venue quotes in another one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
 }
 
 impl OBOrder {
@@ -28,24 +81,173 @@ impl OBOrder {
             side,
             volume,
             price,
+            epoch: 0,
+            rate: None,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// What an `OrderRequest`'s volume is measured in.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeUnit {
+    /// Units of the base asset, e.g. SOL.
+    #[default]
+    Base,
+    /// Notional in the quote currency, e.g. USD to spend on a buy.
+    Quote,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct OrderRequest {
     pub symbol: String,
     pub side: OrderSide,
     pub volume: f64,
+    #[serde(default)]
+    pub unit: VolumeUnit,
+    /// Also return every slice the quote consumed; see `QuoteDetail`.
+    #[serde(default)]
+    pub detailed: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PriceResponse {
     pub symbol: String,
     pub total_volume: f64,
     pub side: OrderSide,
-    pub alpaca_volume: f64,
-    pub kraken_volume: f64,
-    pub bybit_volume: f64,
+    /// Volume taken from each venue the quote filled from.
+    pub venue_volumes: BTreeMap<String, f64>,
     pub vwap: f64,
-}
\ No newline at end of file
+    /// Rate each bridged quote currency was converted into the book's quote
+    /// at, weighted by the volume this quote took from it, e.g.
+    /// `USDT -> 0.9998`. Empty when no converted volume was taken.
+    #[serde(default)]
+    pub conversion_rates: BTreeMap<String, f64>,
+    /// False when the book ran out before `total_volume` reached the request.
+    #[serde(default)]
+    pub fully_filled: bool,
+    /// Present when the request asked for `detailed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<QuoteDetail>,
+}
+
+/// Volume taken from one venue at one price while filling a quote.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FillSlice {
+    pub exchange: String,
+    pub price: f64,
+    pub volume: f64,
+}
+
+/// How a quote walked the book, for routing child limit orders.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QuoteDetail {
+    /// Slices in the order they were filled, best price first.
+    pub slices: Vec<FillSlice>,
+    /// Price of the last slice filled.
+    pub worst_price: f64,
+    /// Midpoint of the best bid and ask, when both sides have liquidity.
+    pub mid_price: Option<f64>,
+    /// How far the VWAP is from `mid_price` against the taker, in basis points.
+    pub slippage_bps: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VenueLevel {
+    pub exchange: String,
+    pub volume: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PriceLevel {
+    pub price: f64,
+    pub total_volume: f64,
+    pub venues: Vec<VenueLevel>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VenueQuote {
+    pub exchange: String,
+    pub price: f64,
+    pub volume: f64,
+}
+
+/// Updates the book has applied from each venue, counted across both sides.
+/// Two reads with equal sequences saw the same book.
+pub type VenueSequences = BTreeMap<String, u64>;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BookSnapshot {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    #[serde(default)]
+    pub venue_sequences: VenueSequences,
+}
+
+/// Best price per venue on each side, from one consistent book state.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VenueTops {
+    pub bids: Vec<VenueQuote>,
+    pub asks: Vec<VenueQuote>,
+    pub venue_sequences: VenueSequences,
+}
+
+/// One venue's resting volume at a price after a change; zero volume means
+/// the venue left the level. `sequence` counts changes per side.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LevelUpdate {
+    pub side: OrderSide,
+    pub sequence: u64,
+    pub exchange: String,
+    pub price: f64,
+    pub volume: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SequencedBookSnapshot {
+    pub bid_sequence: u64,
+    pub ask_sequence: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    #[serde(default)]
+    pub venue_sequences: VenueSequences,
+}
+
+/// A child order a venue accepted while routing. `price` is the quoted VWAP
+/// the order was routed at, not a venue execution report.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Fill {
+    pub exchange: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub volume: f64,
+    pub price: f64,
+    pub timestamp_ms: u64,
+}
+
+/// A public trade printed on one venue, priced in the pair's own quote
+/// currency. `side` is the aggressor: `Buy` when a taker lifted the offer.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Trade {
+    pub exchange: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub price: f64,
+    pub size: f64,
+    /// Venue execution time in Unix milliseconds.
+    pub timestamp_ms: u64,
+}
+
+/// Trading rules for one symbol on one venue, in base and quote units.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct InstrumentInfo {
+    pub exchange: String,
+    pub symbol: String,
+    /// Smallest price increment.
+    pub tick_size: f64,
+    /// Smallest quantity increment.
+    pub lot_size: f64,
+    pub min_qty: f64,
+    /// Smallest order value in the quote currency, 0 when the venue has none.
+    pub min_notional: f64,
+}